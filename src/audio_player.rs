@@ -1,3 +1,4 @@
+use crate::config_file::AudioSection;
 use sdl3::Sdl;
 use sdl3::audio::{AudioCallback, AudioFormat, AudioSpec, AudioStream, AudioStreamWithCallback};
 
@@ -8,7 +9,35 @@ struct SquareWave {
 }
 
 pub struct AudioPlayer {
-    audio_device: AudioStreamWithCallback<SquareWave>,
+    audio_device: Option<AudioStreamWithCallback<SquareWave>>,
+}
+
+/// Settings of the beep played while the sound timer is active
+#[derive(Debug, Clone)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    pub volume: f32,
+    pub frequency: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            volume: 0.25,
+            frequency: 440.0,
+        }
+    }
+}
+
+impl AudioConfig {
+    pub fn merge(self, section: &AudioSection) -> Self {
+        Self {
+            enabled: section.enabled.unwrap_or(self.enabled),
+            volume: section.volume.unwrap_or(self.volume).clamp(0.0, 1.0),
+            frequency: section.frequency.unwrap_or(self.frequency),
+        }
+    }
 }
 
 impl AudioCallback<f32> for SquareWave {
@@ -28,7 +57,10 @@ impl AudioCallback<f32> for SquareWave {
 }
 
 impl AudioPlayer {
-    pub fn new(sdl_context: &Sdl) -> AudioPlayer {
+    pub fn new(sdl_context: &Sdl, config: &AudioConfig) -> AudioPlayer {
+        if !config.enabled {
+            return Self { audio_device: None };
+        }
         let audio_subsystem = sdl_context.audio().unwrap();
 
         let source_freq = 44100;
@@ -41,22 +73,26 @@ impl AudioPlayer {
             .open_playback_stream(
                 &source_spec,
                 SquareWave {
-                    phase_inc: 440.0 / source_freq as f32,
+                    phase_inc: config.frequency / source_freq as f32,
                     phase: 0.0,
-                    volume: 0.25,
+                    volume: config.volume,
                 },
             )
             .unwrap();
         Self {
-            audio_device: device,
+            audio_device: Some(device),
         }
     }
 
     pub fn play_sound(&self) {
-        self.audio_device.resume().expect("Can't resume audio !");
+        if let Some(device) = &self.audio_device {
+            device.resume().expect("Can't resume audio !");
+        }
     }
 
     pub fn pause_sound(&self) {
-        self.audio_device.pause().expect("Can't resume audio !");
+        if let Some(device) = &self.audio_device {
+            device.pause().expect("Can't resume audio !");
+        }
     }
 }
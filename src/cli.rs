@@ -1,61 +1,288 @@
+use crate::audio_player::AudioConfig;
+use crate::clock::{EmulationRate, TimerModel};
 use crate::config::Config;
+use crate::config_file::{ColorsSection, ConfigFile};
+use crate::control_server::ControlAddress;
+use crate::game_window::DisplayMode;
 use crate::interpreter::InterpreterVariant;
-use crate::screen_config::{Colors, Dimensions};
-use clap::Parser;
+use crate::key_mapping::{KeyLayout, KeyNames, parse_binding};
+use crate::phosphor_filter::{FilterMode, FilterSettings};
+use crate::random::RandomMode;
+use crate::rom_profile::{RomProfiles, RomSettings};
+use crate::screen_config::{Dimensions, Palette};
+use crate::vip_timing::TimingMode;
 use clap::ValueEnum;
 use clap::ValueHint;
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 // Test
 #[derive(Parser, Debug)]
 #[command(version, about, long_about=None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    /// Path for the ROM to load
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path for the ROM to load, the ROM launcher opens when omitted
     #[arg(short='f', long, value_hint = ValueHint::FilePath, value_name = "FILE")]
-    rom_path: PathBuf,
+    rom_path: Option<PathBuf>,
 
-    /// Color of the foreground of the screen
-    #[arg(value_enum, short='1', long, default_value_t=ColorValue::Green, value_name = "COLOR")]
-    on_color: ColorValue,
+    /// Folder listed by the ROM browser (F5), relative ROM paths are also looked up there
+    #[arg(long, value_hint = ValueHint::DirPath, value_name = "FOLDER")]
+    rom_folder: Option<PathBuf>,
 
-    /// Color of the background of the screen
-    #[arg(value_enum, short='0', long, default_value_t=ColorValue::Black, value_name = "COLOR")]
-    off_color: ColorValue,
+    /// Configuration file, defaults to chip8/config.toml in the user configuration folder
+    #[arg(short, long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+    pub config: Option<PathBuf>,
 
-    /// Color of the highlighted text (like pressed keys)
-    #[arg(value_enum, short, long, default_value_t=ColorValue::Red, value_name = "COLOR")]
-    alt_color: ColorValue,
+    /// Palette of the screen and the text, can be changed with Tab [default: classic]
+    #[arg(value_enum, long, value_name = "PALETTE")]
+    palette: Option<Palette>,
 
-    /// Variant of the CHIP-8 interpreter
-    #[arg(value_enum, short, long, default_value_t=InterpreterVariant::Chip48, value_name = "VARIANT")]
-    interpreter_variant: InterpreterVariant,
+    /// Color of the foreground of the screen, a name or #RRGGBB [default: green]
+    #[arg(short = '1', long, value_name = "COLOR")]
+    on_color: Option<ColorValue>,
 
-    /// Number of instructions executed every frame
-    #[arg(short='p', long="ipf", default_value_t = 11, value_name = "INSTRUCTIONS PER FRAME")]
-    instructions_per_frame: u8,
+    /// Color of the background of the screen, a name or #RRGGBB [default: black]
+    #[arg(short = '0', long, value_name = "COLOR")]
+    off_color: Option<ColorValue>,
+
+    /// Color of the highlighted text (like pressed keys), a name or #RRGGBB [default: red]
+    #[arg(short, long, value_name = "COLOR")]
+    alt_color: Option<ColorValue>,
+
+    /// Variant of the CHIP-8 interpreter [default: chip48]
+    #[arg(value_enum, short, long, value_name = "VARIANT")]
+    interpreter_variant: Option<InterpreterVariant>,
+
+    /// Number of instructions executed every frame, at 60 frames per second
+    #[arg(short = 'p', long = "ipf", value_name = "INSTRUCTIONS PER FRAME")]
+    instructions_per_frame: Option<u8>,
+
+    /// Number of instructions executed every second [default: 700]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "HZ", conflicts_with = "instructions_per_frame")]
+    speed: Option<u32>,
+
+    /// Measure the speed in instructions, or in COSMAC VIP machine cycles for authentic speed
+    /// [default: instructions]
+    #[arg(value_enum, long, value_name = "MODE")]
+    timing: Option<TimingMode>,
+
+    /// Fast-forward or slow motion, can be changed with F6/F7 [default: normal]
+    #[arg(value_enum, long, value_name = "RATE")]
+    rate: Option<EmulationRate>,
+
+    /// How the delay and sound timers tick, movies always use instructions [default: instructions]
+    #[arg(value_enum, long, value_name = "MODEL")]
+    timers: Option<TimerModel>,
+
+    /// Keyboard layout the key mapping starts from [default: qwerty]
+    #[arg(value_enum, short, long, value_name = "LAYOUT")]
+    key_layout: Option<KeyLayout>,
+
+    /// Bind a keypad key to keyboard keys (SDL scancode names), like 5=W,Up. Can be repeated
+    #[arg(long, value_parser = parse_binding, value_name = "KEY=NAMES")]
+    bind: Vec<(String, KeyNames)>,
+
+    /// Screen resolution [default: full-hd]
+    #[arg(value_enum, short, long, value_name = "VARIANT")]
+    resolution: Option<Resolution>,
+
+    /// Window size in pixels, like 1600x900, instead of a preset resolution
+    #[arg(long, value_parser = parse_window_size, value_name = "WxH", conflicts_with = "resolution")]
+    window_size: Option<Dimensions>,
+
+    /// Debugger panels, game only or fullscreen game, can be changed with F11 [default: debugger]
+    #[arg(value_enum, short, long, value_name = "MODE")]
+    display: Option<DisplayMode>,
 
-    /// Screen resolution
-    #[arg(value_enum, short, long, default_value_t=Resolution::FullHD, value_name = "VARIANT")]
-    resolution: Resolution,
+    /// Blend the last frames of the game to reduce flicker, for display only [default: none]
+    #[arg(value_enum, long, value_name = "FILTER")]
+    filter: Option<FilterMode>,
+
+    /// Serve JSON-RPC control requests on a loopback HOST:PORT or unix:PATH
+    #[arg(long, value_name = "ADDRESS")]
+    control: Option<ControlAddress>,
+
+    /// Rhai script hooked into the emulation loop
+    #[arg(short, long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+    script: Option<PathBuf>,
+
+    /// Record the keypad state of every frame into a movie file
+    #[arg(long, value_hint = ValueHint::FilePath, value_name = "FILE", requires = "rom_path")]
+    record: Option<PathBuf>,
+
+    /// Replay a movie file previously recorded with --record
+    #[arg(
+        long,
+        value_hint = ValueHint::FilePath,
+        value_name = "FILE",
+        conflicts_with = "record",
+        requires = "rom_path"
+    )]
+    replay: Option<PathBuf>,
+
+    /// Seed of the random number generator, picked at random when omitted
+    #[arg(long, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// Random number generator used by the CXNN instruction [default: seeded]
+    #[arg(value_enum, long, value_name = "MODE")]
+    random: Option<RandomMode>,
 }
 
 impl Cli {
-    pub fn to_config(&self) -> Config {
-        let (width, height) = self.resolution.to_window_dimensions();
-        let dimensions = Dimensions::new(width, height);
-        let colors = Colors::new(self.on_color, self.off_color, self.alt_color);
-        Config::new(
-            self.rom_path.clone(),
-            self.interpreter_variant,
-            dimensions,
-            colors,
-            self.instructions_per_frame,
-        )
+    /// Merges the arguments with the configuration file, arguments taking precedence
+    pub fn to_config(&self, file: &ConfigFile) -> Result<Config, String> {
+        let file_window_size = file
+            .window_size
+            .as_deref()
+            .map(parse_window_size)
+            .transpose()?;
+        // The command line wins, whether it gives a preset or a size
+        let dimensions = match (self.window_size, self.resolution) {
+            (Some(dimensions), _) => dimensions,
+            (None, Some(resolution)) => resolution.window_dimensions(),
+            (None, None) => file_window_size.unwrap_or_else(|| {
+                file.resolution
+                    .unwrap_or(Resolution::FullHD)
+                    .window_dimensions()
+            }),
+        };
+        let rom_folder = self.rom_folder.clone().or(file.rom_folder.clone());
+        let rom_path = self
+            .rom_path
+            .clone()
+            .map(|rom_path| resolve_rom_path(rom_path, rom_folder.as_deref()));
+        let mut config = Config::new(rom_path, dimensions);
+        config.rom_folder = rom_folder;
+        config.overrides = RomSettings {
+            name: None,
+            interpreter_variant: self.interpreter_variant,
+            instructions_per_frame: self.instructions_per_frame,
+            speed: self.speed,
+            timing: self.timing,
+            colors: ColorsSection {
+                palette: self.palette,
+                on: self.on_color,
+                off: self.off_color,
+                alt: self.alt_color,
+            },
+            key_layout: self.key_layout,
+            keys: self.bind.iter().cloned().collect(),
+            gamepad: HashMap::new(),
+        };
+        config.defaults = file.rom_settings();
+        config.rom_profiles = RomProfiles::load(
+            file.profiles_path().as_deref(),
+            file.rom_database.as_deref(),
+        )?;
+        config.profiles_path = file.profiles_save_path();
+        config.select_rom_profile(None)?;
+        config.audio = AudioConfig::default().merge(&file.audio);
+        config.control_address = self.control.clone();
+        config.script_path = self.script.clone();
+        config.record_path = self.record.clone();
+        config.replay_path = self.replay.clone();
+        config.seed = self.seed;
+        config.rate = self.rate.unwrap_or_default();
+        config.timer_model = self.timers.or(file.timers).unwrap_or_default();
+        self.check_movie_timers(config.timer_model)?;
+        config.display_mode = self.display.or(file.display).unwrap_or_default();
+        file.layout.validate()?;
+        config.layout = file.layout.clone();
+        config.filter = FilterSettings {
+            mode: self.filter.or(file.filter.mode),
+            ..file.filter.clone()
+        };
+        config.random_mode = self.random.or(file.random).unwrap_or(RandomMode::Seeded);
+        Ok(config)
+    }
+
+    /// Movies only replay exactly when the timers tick with the instructions
+    fn check_movie_timers(&self, timer_model: TimerModel) -> Result<(), String> {
+        if (self.record.is_some() || self.replay.is_some())
+            && timer_model != TimerModel::Instructions
+        {
+            let name = timer_model
+                .to_possible_value()
+                .expect("Models are never skipped");
+            return Err(format!(
+                "Movies need the instructions timer model, not {}",
+                name.get_name()
+            ));
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
-#[value(rename_all = "kebab-case")]
+/// Looks for relative ROM paths missing from the working directory in the ROM folder
+fn resolve_rom_path(rom_path: PathBuf, rom_folder: Option<&Path>) -> PathBuf {
+    match rom_folder {
+        Some(folder) if rom_path.is_relative() && !rom_path.exists() => folder.join(rom_path),
+        _ => rom_path,
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a ROM without window and compare the final screen to a reference
+    Test(TestArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TestArgs {
+    /// Path for the ROM to run
+    #[arg(value_hint = ValueHint::FilePath, value_name = "ROM")]
+    pub rom_path: PathBuf,
+
+    /// Number of frames to run before comparing the screen
+    #[arg(long, default_value_t = 600, value_name = "FRAMES")]
+    pub frames: u32,
+
+    /// Reference screen, an ASCII-art text file ('#' for lit pixels) or a PNG image
+    #[arg(long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+    pub expect: Option<PathBuf>,
+
+    /// Write the final screen to a text file or a PNG image
+    #[arg(long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+    pub dump: Option<PathBuf>,
+
+    /// Key events to feed, one `FRAME KEY down|up` line each
+    #[arg(long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+    pub keys: Option<PathBuf>,
+
+    /// Variant of the CHIP-8 interpreter
+    #[arg(value_enum, short, long, default_value_t=InterpreterVariant::Chip48, value_name = "VARIANT")]
+    pub interpreter_variant: InterpreterVariant,
+
+    /// Number of instructions executed every frame
+    #[arg(
+        short = 'p',
+        long = "ipf",
+        default_value_t = 11,
+        value_name = "INSTRUCTIONS PER FRAME"
+    )]
+    pub instructions_per_frame: u8,
+
+    /// Number of instructions executed every second, instead of the IPF
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "HZ", conflicts_with = "instructions_per_frame")]
+    pub speed: Option<u32>,
+
+    /// Seed of the random number generator
+    #[arg(long, default_value_t = 0, value_name = "SEED")]
+    pub seed: u64,
+
+    /// Random number generator used by the CXNN instruction
+    #[arg(value_enum, long, default_value_t=RandomMode::Seeded, value_name = "MODE")]
+    pub random: RandomMode,
+}
+
+/// A named color, or any color written `#RRGGBB`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ColorValue {
     Green,
     Red,
@@ -67,6 +294,51 @@ pub enum ColorValue {
     Black,
     Grey,
     Gray,
+    Rgb(u8, u8, u8),
+}
+
+const COLOR_NAMES: [(&str, ColorValue); 10] = [
+    ("green", ColorValue::Green),
+    ("red", ColorValue::Red),
+    ("yellow", ColorValue::Yellow),
+    ("blue", ColorValue::Blue),
+    ("magenta", ColorValue::Magenta),
+    ("cyan", ColorValue::Cyan),
+    ("white", ColorValue::White),
+    ("black", ColorValue::Black),
+    ("grey", ColorValue::Grey),
+    ("gray", ColorValue::Gray),
+];
+
+impl FromStr for ColorValue {
+    type Err = String;
+
+    fn from_str(color: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = color.strip_prefix('#') {
+            // from_str_radix alone would accept signs like #+1+2+3
+            let channel = |index: usize| {
+                hex.get(index..index + 2)
+                    .filter(|channel| channel.bytes().all(|digit| digit.is_ascii_hexdigit()))
+                    .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+            };
+            return match (hex.len(), channel(0), channel(2), channel(4)) {
+                (6, Some(red), Some(green), Some(blue)) => Ok(ColorValue::Rgb(red, green, blue)),
+                _ => Err(format!("Invalid color {}, expected #RRGGBB", color)),
+            };
+        }
+        COLOR_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(color))
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                let names: Vec<&str> = COLOR_NAMES.iter().map(|(name, _)| *name).collect();
+                format!(
+                    "Invalid color {}, expected #RRGGBB or one of {}",
+                    color,
+                    names.join(", ")
+                )
+            })
+    }
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -89,7 +361,7 @@ pub enum Resolution {
 }
 
 impl Resolution {
-    pub fn to_window_dimensions(&self) -> (usize, usize) {
+    pub fn window_dimensions(self) -> Dimensions {
         let (width, height): (usize, usize) = match self {
             Resolution::SuperVGA => (800, 600),
             Resolution::HD => (1280, 720),
@@ -99,6 +371,59 @@ impl Resolution {
             Resolution::QuadHD => (2560, 1440),
             Resolution::UltraWideQHD => (3440, 1440),
         };
-        (width, height)
+        Dimensions::new(width, height)
+    }
+}
+
+/// Parses a window size written `WIDTHxHEIGHT`
+pub fn parse_window_size(size: &str) -> Result<Dimensions, String> {
+    let invalid = || format!("Expected WIDTHxHEIGHT, got {}", size);
+    let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok(Dimensions::new(width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_movie_timers() {
+        let cli =
+            |args: &[&str]| Cli::try_parse_from([&["chip8", "-f", "pong.ch8"], args].concat());
+        let record = cli(&["--record", "pong.c8m"]).unwrap();
+        assert!(record.check_movie_timers(TimerModel::Instructions).is_ok());
+        assert!(record.check_movie_timers(TimerModel::RealTime).is_err());
+        let replay = cli(&["--replay", "pong.c8m"]).unwrap();
+        assert!(replay.check_movie_timers(TimerModel::Frozen).is_err());
+        let play = cli(&[]).unwrap();
+        assert!(play.check_movie_timers(TimerModel::Frozen).is_ok());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!("White".parse(), Ok(ColorValue::White));
+        assert_eq!("#ffcc00".parse(), Ok(ColorValue::Rgb(0xFF, 0xCC, 0x00)));
+        assert!("#ffcc0".parse::<ColorValue>().is_err());
+        assert!("#ffcc0g".parse::<ColorValue>().is_err());
+        assert!("#+1+2+3".parse::<ColorValue>().is_err());
+        assert!("#-1ffff".parse::<ColorValue>().is_err());
+        for length in ["#", "#f", "#fff", "#ffcc000", "#ffcc00ff"] {
+            assert!(length.parse::<ColorValue>().is_err(), "{}", length);
+        }
+        // Multi-byte characters must not split the channels
+        assert!("#ffcé0".parse::<ColorValue>().is_err());
+        assert!("orange".parse::<ColorValue>().is_err());
+    }
+
+    #[test]
+    fn test_parse_window_size() {
+        let dimensions = parse_window_size("1600x900").unwrap();
+        assert_eq!((dimensions.width, dimensions.height), (1600, 900));
+        assert!(parse_window_size("1600").is_err());
+        assert!(parse_window_size("0x900").is_err());
+        assert!(parse_window_size("widexhigh").is_err());
     }
 }
@@ -0,0 +1,387 @@
+use clap::ValueEnum;
+use std::time::{Duration, Instant};
+
+/// Default number of instructions executed every second
+pub const TICK_FREQUENCY: u32 = 700;
+/// Frequency of the delay and sound timers, and of the frames of `advance_frame`
+///
+/// Cycles are counted in 1/60 of instruction so that the timer periods and the frames are
+/// whole numbers of them.
+const TIMER_FREQUENCY: u64 = 60;
+/// Longest time caught up at once, so that a slow frame does not make the emulation race
+const MAX_CATCH_UP: Duration = Duration::from_millis(100);
+
+/// Rate of the emulation compared to real time, for fast-forward and slow motion
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum EmulationRate {
+    Quarter,
+    Half,
+    #[default]
+    Normal,
+    Double,
+    Quadruple,
+    /// As fast as the host allows
+    Unthrottled,
+}
+
+const RATES: [EmulationRate; 6] = [
+    EmulationRate::Quarter,
+    EmulationRate::Half,
+    EmulationRate::Normal,
+    EmulationRate::Double,
+    EmulationRate::Quadruple,
+    EmulationRate::Unthrottled,
+];
+
+impl EmulationRate {
+    /// Emulated time per real time, `None` when unthrottled
+    pub fn factor(self) -> Option<f64> {
+        match self {
+            EmulationRate::Quarter => Some(0.25),
+            EmulationRate::Half => Some(0.5),
+            EmulationRate::Normal => Some(1.0),
+            EmulationRate::Double => Some(2.0),
+            EmulationRate::Quadruple => Some(4.0),
+            EmulationRate::Unthrottled => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            EmulationRate::Quarter => "x1/4",
+            EmulationRate::Half => "x1/2",
+            EmulationRate::Normal => "x1",
+            EmulationRate::Double => "x2",
+            EmulationRate::Quadruple => "x4",
+            EmulationRate::Unthrottled => "MAX",
+        }
+    }
+
+    pub fn faster(self) -> Self {
+        let index = RATES.iter().position(|rate| *rate == self).unwrap_or(0);
+        RATES[(index + 1).min(RATES.len() - 1)]
+    }
+
+    pub fn slower(self) -> Self {
+        let index = RATES.iter().position(|rate| *rate == self).unwrap_or(0);
+        RATES[index.saturating_sub(1)]
+    }
+}
+
+/// How the delay and sound timers follow the emulation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimerModel {
+    /// Tick every `speed / 60` instructions, stepping with F2 included, like the recorded
+    /// movies always do
+    #[default]
+    Instructions,
+    /// Tick at 60 Hz of emulated time while running, frozen while paused or stepping
+    Frozen,
+    /// Tick at 60 Hz, even while paused or stepping
+    RealTime,
+}
+
+/// Next speed up : doubled below 60 Hz, then one more instruction per frame (60 Hz) up to
+/// 1200 Hz, then about 10 % more
+pub fn faster_speed(speed: u32) -> u32 {
+    if speed < 60 {
+        return (speed * 2).min(60);
+    }
+    speed.saturating_add(speed_step(speed))
+}
+
+/// Next speed down, the reverse of `faster_speed`
+pub fn slower_speed(speed: u32) -> u32 {
+    if speed <= 60 {
+        return (speed / 2).max(1);
+    }
+    speed.saturating_sub(speed_step(speed - 1)).max(60)
+}
+
+fn speed_step(speed: u32) -> u32 {
+    60 * (speed / 600).max(1)
+}
+
+/// Schedules the instructions at a given frequency, independently of the frame rate
+///
+/// Fractions of instructions are carried over from one frame to the next. With the
+/// `Instructions` timer model the timers tick every `frequency / 60` instructions, that is at
+/// 60 Hz of emulated time, which keeps them in step with the instructions whatever the frame
+/// rate and lets replays tick them at the same instructions as the recording. The other
+/// models tick them with the time given to the clock.
+///
+/// With the VIP timing the clock counts machine cycles rather than instructions, every
+/// instruction costing its own number of them.
+pub struct Clock {
+    /// Instructions, or machine cycles, per second
+    frequency: u32,
+    timer_model: TimerModel,
+    /// Timer ticks due with the time based models, not yet taken
+    pending_ticks: u32,
+    /// Fraction of an instruction left over from the previous frames, in 1/60 of instruction
+    pending_cycles: u64,
+    /// Time left before the next timer tick, in 1/60 of instruction
+    cycles_to_tick: i64,
+    /// Machine cycles spent beyond the previous budget, taken from the next one
+    overrun: u64,
+    last_advance: Option<Instant>,
+}
+
+impl Clock {
+    pub fn new(frequency: u32, timer_model: TimerModel) -> Self {
+        let mut clock = Self {
+            frequency: frequency.max(1),
+            timer_model,
+            pending_ticks: 0,
+            pending_cycles: 0,
+            cycles_to_tick: 0,
+            overrun: 0,
+            last_advance: None,
+        };
+        clock.reset();
+        clock
+    }
+
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    pub fn timer_model(&self) -> TimerModel {
+        self.timer_model
+    }
+
+    pub fn set_frequency(&mut self, frequency: u32) {
+        self.frequency = frequency.max(1);
+        self.cycles_to_tick = self.cycles_to_tick.min(self.tick_period());
+    }
+
+    /// Starts over from the beginning of a timer period, as after a power cycle
+    pub fn reset(&mut self) {
+        self.pending_cycles = 0;
+        self.pending_ticks = 0;
+        self.overrun = 0;
+        self.cycles_to_tick = self.tick_period();
+        self.last_advance = None;
+    }
+
+    /// Forgets the time elapsed since the last advance, when it was spent elsewhere
+    pub fn pause(&mut self) {
+        self.last_advance = None;
+    }
+
+    /// Lets the time pass while the emulation does not run, only the real-time timers see it
+    pub fn idle(&mut self) {
+        if self.timer_model != TimerModel::RealTime {
+            self.last_advance = None;
+            return;
+        }
+        let elapsed = self.elapsed_since_last_advance();
+        self.idle_for(elapsed);
+    }
+
+    /// Instructions due for the real time elapsed since the last advance, scaled by `rate`,
+    /// one frame worth of them when the clock was just started or paused
+    pub fn advance_real_time(&mut self, rate: f64) -> u64 {
+        let elapsed = self.elapsed_since_last_advance();
+        self.advance_for(elapsed, rate)
+    }
+
+    /// Instructions due for one 60 Hz frame
+    pub fn advance_frame(&mut self) -> u64 {
+        self.add_cycles(self.frequency as u64)
+    }
+
+    /// Counts an executed instruction costing `cost` cycles, 1 unless with the VIP timing,
+    /// returns how many timer ticks are due after it
+    pub fn cycle(&mut self, cost: u32) -> u32 {
+        if self.timer_model != TimerModel::Instructions {
+            return 0;
+        }
+        self.count_time(TIMER_FREQUENCY * cost as u64)
+    }
+
+    /// Whole cycles left before the next timer tick, which is the display interrupt of the
+    /// COSMAC VIP with the VIP timing
+    pub fn cycles_to_tick(&self) -> u32 {
+        (self.cycles_to_tick.max(0) as u64).div_ceil(TIMER_FREQUENCY) as u32
+    }
+
+    /// Records that the last instruction of a budget went beyond it by `cycles`
+    pub fn overrun(&mut self, cycles: u64) {
+        self.overrun += cycles;
+    }
+
+    /// Timer ticks due with the time based models since the last call
+    pub fn take_timer_ticks(&mut self) -> u32 {
+        std::mem::take(&mut self.pending_ticks)
+    }
+
+    fn elapsed_since_last_advance(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = self.last_advance.map_or(Duration::ZERO, |last| now - last);
+        self.last_advance = Some(now);
+        elapsed
+    }
+
+    fn idle_for(&mut self, elapsed: Duration) {
+        let cycles = self.cycles_for(elapsed, 1.0);
+        self.pending_ticks += self.count_time(cycles);
+    }
+
+    fn advance_for(&mut self, elapsed: Duration, rate: f64) -> u64 {
+        let cycles = self.cycles_for(elapsed, rate);
+        self.add_cycles(cycles)
+    }
+
+    /// Cycles in 1/60 of instruction for a duration, one frame worth of them when it is zero
+    fn cycles_for(&self, elapsed: Duration, rate: f64) -> u64 {
+        let seconds = match elapsed {
+            Duration::ZERO => 1.0 / TIMER_FREQUENCY as f64,
+            elapsed => elapsed.min(MAX_CATCH_UP).as_secs_f64(),
+        };
+        (seconds * rate * (self.frequency as u64 * TIMER_FREQUENCY) as f64).round() as u64
+    }
+
+    /// Moves towards the next timer tick by a number of cycles in 1/60 of instruction,
+    /// returns how many ticks are due
+    fn count_time(&mut self, cycles: u64) -> u32 {
+        self.cycles_to_tick -= cycles as i64;
+        let mut ticks = 0;
+        while self.cycles_to_tick <= 0 {
+            self.cycles_to_tick += self.tick_period();
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// Adds cycles counted in 1/60 of instruction, returns the whole instructions due
+    fn add_cycles(&mut self, cycles: u64) -> u64 {
+        if self.timer_model != TimerModel::Instructions {
+            self.pending_ticks += self.count_time(cycles);
+        }
+        self.pending_cycles += cycles;
+        let whole = self.pending_cycles / TIMER_FREQUENCY;
+        self.pending_cycles %= TIMER_FREQUENCY;
+        let overrun = self.overrun.min(whole);
+        self.overrun -= overrun;
+        whole - overrun
+    }
+
+    fn tick_period(&self) -> i64 {
+        self.frequency as i64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fractional_cycles() {
+        let mut clock = Clock::new(90, TimerModel::Instructions);
+        // 1.5 instructions per frame
+        let cycles: Vec<u64> = (0..4).map(|_| clock.advance_frame()).collect();
+        assert_eq!(cycles, [1, 2, 1, 2]);
+
+        let mut clock = Clock::new(1, TimerModel::Instructions);
+        assert_eq!((0..60).map(|_| clock.advance_frame()).sum::<u64>(), 1);
+        let mut clock = Clock::new(3_000_000, TimerModel::Instructions);
+        assert_eq!(clock.advance_frame(), 50_000);
+    }
+
+    #[test]
+    fn test_speed_steps() {
+        assert_eq!(faster_speed(700), 760);
+        assert_eq!(slower_speed(760), 700);
+        assert_eq!(faster_speed(6000), 6600);
+        assert_eq!(slower_speed(6600), 6000);
+        assert_eq!(slower_speed(90), 60);
+        assert_eq!(slower_speed(60), 30);
+        assert_eq!(slower_speed(1), 1);
+        assert_eq!(faster_speed(30), 60);
+        assert_eq!(faster_speed(u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn test_rates() {
+        assert_eq!(EmulationRate::Normal.faster(), EmulationRate::Double);
+        assert_eq!(
+            EmulationRate::Unthrottled.faster(),
+            EmulationRate::Unthrottled
+        );
+        assert_eq!(EmulationRate::Quarter.slower(), EmulationRate::Quarter);
+        assert_eq!(EmulationRate::Half.slower(), EmulationRate::Quarter);
+    }
+
+    #[test]
+    fn test_timers_tick_at_60_hz() {
+        for frequency in [1, 60, 660, 700, 1_000_000] {
+            let mut clock = Clock::new(frequency, TimerModel::Instructions);
+            let ticks: u32 = (0..frequency).map(|_| clock.cycle(1)).sum();
+            assert_eq!(ticks, 60, "at {} Hz", frequency);
+        }
+        // 11 instructions per tick at 660 Hz
+        let mut clock = Clock::new(660, TimerModel::Instructions);
+        let ticks: Vec<u32> = (0..11).map(|_| clock.cycle(1)).collect();
+        assert_eq!(ticks[..10], [0; 10]);
+        assert_eq!(ticks[10], 1);
+    }
+
+    #[test]
+    fn test_cycles_to_tick() {
+        let mut clock = Clock::new(660, TimerModel::Instructions);
+        assert_eq!(clock.cycles_to_tick(), 11);
+        clock.cycle(4);
+        assert_eq!(clock.cycles_to_tick(), 7);
+        // Waiting for the tick brings it
+        assert_eq!(clock.cycle(clock.cycles_to_tick()), 1);
+        assert_eq!(clock.cycles_to_tick(), 11);
+    }
+
+    #[test]
+    fn test_overrun() {
+        let mut clock = Clock::new(600, TimerModel::Instructions);
+        clock.overrun(15);
+        assert_eq!(clock.advance_frame(), 0);
+        assert_eq!(clock.advance_frame(), 5);
+        // Costly instructions bring the timers closer to their tick
+        assert_eq!(clock.cycle(9), 0);
+        assert_eq!(clock.cycle(1), 1);
+    }
+
+    #[test]
+    fn test_instructions_timer_model() {
+        let mut clock = Clock::new(660, TimerModel::Instructions);
+        // Time alone does not tick the timers, 11 steps do
+        clock.advance_frame();
+        clock.advance_for(Duration::from_secs(1), 1.0);
+        clock.idle();
+        assert_eq!(clock.take_timer_ticks(), 0);
+        assert_eq!((0..11).map(|_| clock.cycle(1)).sum::<u32>(), 1);
+    }
+
+    #[test]
+    fn test_frozen_timer_model() {
+        let mut clock = Clock::new(660, TimerModel::Frozen);
+        assert_eq!((0..22).map(|_| clock.cycle(1)).sum::<u32>(), 0);
+        clock.idle();
+        assert_eq!(clock.take_timer_ticks(), 0);
+        clock.advance_frame();
+        clock.advance_for(Duration::from_millis(50), 1.0);
+        assert_eq!(clock.take_timer_ticks(), 4);
+        // Fast-forward ticks them faster, like the instructions
+        clock.advance_for(Duration::from_millis(50), 2.0);
+        assert_eq!(clock.take_timer_ticks(), 6);
+    }
+
+    #[test]
+    fn test_real_time_timer_model() {
+        let mut clock = Clock::new(660, TimerModel::RealTime);
+        assert_eq!(clock.cycle(1), 0);
+        clock.idle_for(Duration::from_millis(50));
+        assert_eq!(clock.take_timer_ticks(), 3);
+        assert_eq!(clock.take_timer_ticks(), 0);
+        clock.advance_frame();
+        assert_eq!(clock.take_timer_ticks(), 1);
+    }
+}
@@ -1,27 +1,117 @@
+use crate::audio_player::AudioConfig;
+use crate::clock::{EmulationRate, TICK_FREQUENCY, TimerModel};
+use crate::control_server::ControlAddress;
+use crate::game_window::DisplayMode;
+use crate::gamepad_mapping::GamepadMapping;
 use crate::interpreter::InterpreterVariant;
-use crate::screen_config::{Colors, Dimensions, ScreenConfig};
+use crate::key_mapping::KeyMapping;
+use crate::panel_layout::PanelLayout;
+use crate::phosphor_filter::FilterSettings;
+use crate::random::RandomMode;
+use crate::rom_profile::{RomProfiles, RomSettings};
+use crate::screen_config::{Colors, Dimensions, Palette, ScreenConfig};
+use crate::vip_timing::TimingMode;
 use std::path::PathBuf;
 
 pub struct Config {
     pub screen_config: ScreenConfig,
-    pub rom_path: PathBuf,
-    pub instructions_per_frame: u8,
+    /// Palette the colors of the ROM start from, the colors given one by one overriding it
+    pub palette: Palette,
+    pub display_mode: DisplayMode,
+    /// Panels of the debugger and the windows they are in
+    pub layout: PanelLayout,
+    /// Display filter blending the last frames of the game
+    pub filter: FilterSettings,
+    /// ROM to load at start, the ROM launcher opens when there is none
+    pub rom_path: Option<PathBuf>,
+    /// Instructions executed every second
+    pub speed: u32,
+    /// Whether the speed counts instructions or COSMAC VIP machine cycles
+    pub timing: TimingMode,
+    /// Rate of the emulation compared to real time
+    pub rate: EmulationRate,
+    pub timer_model: TimerModel,
     pub interpreter_variant: InterpreterVariant,
+    pub rom_folder: Option<PathBuf>,
+    pub key_mapping: KeyMapping,
+    pub gamepad_mapping: GamepadMapping,
+    pub audio: AudioConfig,
+    pub control_address: Option<ControlAddress>,
+    pub script_path: Option<PathBuf>,
+    pub record_path: Option<PathBuf>,
+    pub replay_path: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub random_mode: RandomMode,
+    /// Per-ROM settings given on the command line, they override the ROM profiles
+    pub overrides: RomSettings,
+    /// Per-ROM settings of the configuration file, the ROM profiles override them
+    pub defaults: RomSettings,
+    pub rom_profiles: RomProfiles,
+    /// Profiles file where the speed tuned at runtime is saved
+    pub profiles_path: Option<PathBuf>,
 }
 
 impl Config {
-    pub fn new(
-        rom_path: PathBuf,
-        interpreter_variant: InterpreterVariant,
-        window_dimensions: Dimensions,
-        colors: Colors,
-        instructions_per_frame: u8,
-    ) -> Self {
+    pub fn new(rom_path: Option<PathBuf>, window_dimensions: Dimensions) -> Self {
+        let (on, off, alt) = Palette::Classic.colors();
+        let colors = Colors::new(on, off, alt);
         Self {
             screen_config: ScreenConfig::new(window_dimensions, colors),
+            palette: Palette::Classic,
+            display_mode: DisplayMode::Debugger,
+            layout: PanelLayout::default(),
+            filter: FilterSettings::default(),
             rom_path,
-            instructions_per_frame,
-            interpreter_variant,
+            speed: TICK_FREQUENCY,
+            timing: TimingMode::Instructions,
+            rate: EmulationRate::Normal,
+            timer_model: TimerModel::Instructions,
+            interpreter_variant: InterpreterVariant::Chip48,
+            rom_folder: None,
+            key_mapping: KeyMapping::default(),
+            gamepad_mapping: GamepadMapping::default(),
+            audio: AudioConfig::default(),
+            control_address: None,
+            script_path: None,
+            record_path: None,
+            replay_path: None,
+            seed: None,
+            random_mode: RandomMode::Seeded,
+            overrides: RomSettings::default(),
+            defaults: RomSettings::default(),
+            rom_profiles: RomProfiles::default(),
+            profiles_path: None,
         }
     }
+
+    /// Selects the variant, speed, colors, keys and gamepad buttons of the ROM with the given hash
+    ///
+    /// Command line settings come first, then the profile of the ROM, then the configuration
+    /// file. Without hash only the command line and the configuration file are used.
+    pub fn select_rom_profile(&mut self, rom_hash: Option<&str>) -> Result<(), String> {
+        let profile = rom_hash
+            .and_then(|hash| self.rom_profiles.get(hash))
+            .cloned()
+            .unwrap_or_default();
+        let settings = self.overrides.clone().or(profile).or(self.defaults.clone());
+        let mut key_mapping = KeyMapping::new(settings.key_layout.unwrap_or_default());
+        key_mapping.apply_bindings(&settings.keys)?;
+        self.key_mapping = key_mapping;
+        let mut gamepad_mapping = GamepadMapping::default();
+        gamepad_mapping.apply_bindings(&settings.gamepad)?;
+        self.gamepad_mapping = gamepad_mapping;
+        self.interpreter_variant = settings
+            .interpreter_variant
+            .unwrap_or(InterpreterVariant::Chip48);
+        self.speed = settings.speed().unwrap_or(TICK_FREQUENCY);
+        self.timing = settings.timing.unwrap_or_default();
+        self.palette = settings.colors.palette.unwrap_or_default();
+        let (on, off, alt) = self.palette.colors();
+        self.screen_config.colors = Colors::new(
+            settings.colors.on.unwrap_or(on),
+            settings.colors.off.unwrap_or(off),
+            settings.colors.alt.unwrap_or(alt),
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,244 @@
+use crate::cli::{ColorValue, Resolution};
+use crate::clock::TimerModel;
+use crate::game_window::DisplayMode;
+use crate::interpreter::InterpreterVariant;
+use crate::key_mapping::{KeyLayout, KeyNames};
+use crate::panel_layout::PanelLayout;
+use crate::phosphor_filter::FilterSettings;
+use crate::random::RandomMode;
+use crate::rom_profile::RomSettings;
+use crate::screen_config::Palette;
+use crate::vip_timing::TimingMode;
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const CONFIG_FOLDER: &str = "chip8";
+const CONFIG_FILE: &str = "config.toml";
+const PROFILES_FILE: &str = "profiles.toml";
+const RECENT_ROMS_FILE: &str = "recent.txt";
+
+/// Settings read from the TOML configuration file, every one of them being optional
+///
+/// Command line arguments take precedence over the file which takes precedence over the
+/// default values. Enumerations use the same names as the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub rom_folder: Option<PathBuf>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub interpreter_variant: Option<InterpreterVariant>,
+    pub instructions_per_frame: Option<u8>,
+    /// Instructions per second, takes precedence over `instructions_per_frame`
+    pub speed: Option<u32>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub timing: Option<TimingMode>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub resolution: Option<Resolution>,
+    /// Window size like `1600x900`, takes precedence over `resolution`
+    pub window_size: Option<String>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub display: Option<DisplayMode>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub random: Option<RandomMode>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub timers: Option<TimerModel>,
+    #[serde(default)]
+    pub colors: ColorsSection,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub key_layout: Option<KeyLayout>,
+    /// Keypad key (hexadecimal digit) to keyboard keys (SDL scancode names)
+    #[serde(default)]
+    pub keys: HashMap<String, KeyNames>,
+    /// Keypad key (hexadecimal digit) to gamepad buttons
+    #[serde(default)]
+    pub gamepad: HashMap<String, KeyNames>,
+    #[serde(default)]
+    pub audio: AudioSection,
+    #[serde(default)]
+    pub layout: PanelLayout,
+    #[serde(default)]
+    pub filter: FilterSettings,
+    /// Per-ROM profiles, defaults to chip8/profiles.toml in the user configuration folder
+    pub profiles: Option<PathBuf>,
+    /// `programs.json` of the community CHIP-8 database
+    pub rom_database: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorsSection {
+    /// The colors given one by one take precedence over the palette
+    #[serde(default, deserialize_with = "value_enum")]
+    pub palette: Option<Palette>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub on: Option<ColorValue>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub off: Option<ColorValue>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub alt: Option<ColorValue>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AudioSection {
+    pub enabled: Option<bool>,
+    pub volume: Option<f32>,
+    pub frequency: Option<f32>,
+}
+
+impl ConfigFile {
+    /// Loads the given file, or the one in the user configuration folder when there is one
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path().filter(|path| path.is_file()) {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read config {} : {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid config {} : {}", path.display(), e))
+    }
+
+    /// Settings of the file that ROM profiles may override
+    pub fn rom_settings(&self) -> RomSettings {
+        RomSettings {
+            name: None,
+            interpreter_variant: self.interpreter_variant,
+            instructions_per_frame: self.instructions_per_frame,
+            speed: self.speed,
+            timing: self.timing,
+            colors: self.colors.clone(),
+            key_layout: self.key_layout,
+            keys: self.keys.clone(),
+            gamepad: self.gamepad.clone(),
+        }
+    }
+
+    /// The profiles file given in the configuration, or the one in the user configuration
+    /// folder when there is one
+    pub fn profiles_path(&self) -> Option<PathBuf> {
+        self.profiles
+            .clone()
+            .or_else(|| default_profiles_path().filter(|path| path.is_file()))
+    }
+
+    /// File where tuned settings are saved, the profiles file even when it does not exist yet
+    pub fn profiles_save_path(&self) -> Option<PathBuf> {
+        self.profiles.clone().or_else(default_profiles_path)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/chip8/config.toml`, falling back on `~/.config` or `%APPDATA%`
+pub fn default_path() -> Option<PathBuf> {
+    Some(config_folder()?.join(CONFIG_FILE))
+}
+
+fn default_profiles_path() -> Option<PathBuf> {
+    Some(config_folder()?.join(PROFILES_FILE))
+}
+
+/// File keeping the recently loaded ROMs, next to the configuration file
+pub fn recent_roms_path() -> Option<PathBuf> {
+    Some(config_folder()?.join(RECENT_ROMS_FILE))
+}
+
+fn config_folder() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config_home.join(CONFIG_FOLDER))
+}
+
+pub(crate) fn value_enum<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ValueEnum,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| T::from_str(&value, true).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Reads a value written as a string, like a `#RRGGBB` color
+pub(crate) fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::phosphor_filter::FilterMode;
+
+    #[test]
+    fn test_parse_config_file() {
+        let file: ConfigFile = toml::from_str(
+            r##"
+            rom_folder = "roms"
+            interpreter_variant = "cosmac-vip"
+            instructions_per_frame = 15
+            resolution = "super-vga"
+            key_layout = "azerty"
+
+            [colors]
+            palette = "amber"
+            on = "white"
+            alt = "#FF6600"
+
+            [keys]
+            "C" = "4"
+            "5" = ["W", "Up"]
+
+            [audio]
+            volume = 0.5
+
+            [filter]
+            mode = "decay"
+            decay = 0.8
+            "##,
+        )
+        .unwrap();
+        assert_eq!(file.rom_folder, Some(PathBuf::from("roms")));
+        assert!(matches!(
+            file.interpreter_variant,
+            Some(InterpreterVariant::CosmacVip)
+        ));
+        assert_eq!(file.instructions_per_frame, Some(15));
+        assert!(matches!(file.resolution, Some(Resolution::SuperVGA)));
+        assert!(matches!(file.colors.on, Some(ColorValue::White)));
+        assert!(file.colors.off.is_none());
+        assert_eq!(file.colors.alt, Some(ColorValue::Rgb(0xFF, 0x66, 0x00)));
+        assert_eq!(file.colors.palette, Some(Palette::Amber));
+        assert_eq!(file.key_layout, Some(KeyLayout::Azerty));
+        assert_eq!(file.keys["C"], KeyNames::One("4".to_string()));
+        assert_eq!(
+            file.keys["5"],
+            KeyNames::Many(vec!["W".to_string(), "Up".to_string()])
+        );
+        assert_eq!(file.audio.volume, Some(0.5));
+        assert!(file.audio.enabled.is_none());
+        assert_eq!(file.filter.mode, Some(FilterMode::Decay));
+        assert_eq!(file.filter.decay, 0.8);
+        assert_eq!(file.filter.frames, 2);
+    }
+
+    #[test]
+    fn test_reject_invalid_config_file() {
+        assert!(toml::from_str::<ConfigFile>("resolution = \"8k\"").is_err());
+        assert!(toml::from_str::<ConfigFile>("unknown_setting = 1").is_err());
+    }
+}
@@ -0,0 +1,416 @@
+use serde_json::{Value, json};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, Sender, TryIter, channel};
+use std::thread;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Address the control server listens on, either `HOST:PORT` or `unix:PATH`
+#[derive(Debug, Clone)]
+pub enum ControlAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for ControlAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(ControlAddress::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(format!("Unix sockets are not supported here : {}", path));
+        }
+        let address = s
+            .parse::<SocketAddr>()
+            .map_err(|e| format!("Invalid control address {} : {}", s, e))?;
+        // Anyone reaching the socket can write the memory and load files
+        if !address.ip().is_loopback() {
+            return Err(format!(
+                "The control server has no authentication and only listens on loopback \
+                 addresses like 127.0.0.1, not {}",
+                address.ip()
+            ));
+        }
+        Ok(ControlAddress::Tcp(address))
+    }
+}
+
+/// A command sent by an external tool through the control socket
+#[derive(Debug)]
+pub enum ControlCommand {
+    Pause,
+    Resume,
+    Step,
+    Reset,
+    ReadMemory { address: usize, length: usize },
+    WriteMemory { address: usize, bytes: Vec<u8> },
+    ReadRegisters,
+    WriteRegisters(Value),
+    PressKey(u8),
+    ReleaseKey(u8),
+    Framebuffer,
+    LoadRom(PathBuf),
+}
+
+/// A parsed command along with the channel used to send the result back to the client
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<Result<Value, String>>,
+}
+
+impl ControlRequest {
+    pub fn respond(self, result: Result<Value, String>) {
+        // The client may have hung up in the meantime, nothing to do about it
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Line-delimited JSON-RPC 2.0 server letting external tools drive the emulator
+///
+/// Connections are served on background threads, parsed requests are queued and
+/// must be processed by the run loop through `ControlServer::pending`.
+pub struct ControlServer {
+    receiver: Receiver<ControlRequest>,
+}
+
+impl ControlServer {
+    pub fn start(address: &ControlAddress) -> std::io::Result<Self> {
+        let (sender, receiver) = channel();
+        match address {
+            ControlAddress::Tcp(addr) => {
+                let listener = TcpListener::bind(addr)?;
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        let Ok(writer) = stream.try_clone() else {
+                            continue;
+                        };
+                        let sender = sender.clone();
+                        thread::spawn(move || serve(BufReader::new(stream), writer, sender));
+                    }
+                });
+            }
+            #[cfg(unix)]
+            ControlAddress::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)?;
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        let Ok(writer) = stream.try_clone() else {
+                            continue;
+                        };
+                        let sender = sender.clone();
+                        thread::spawn(move || serve(BufReader::new(stream), writer, sender));
+                    }
+                });
+            }
+        }
+        Ok(Self { receiver })
+    }
+
+    /// Requests received since the last call, never blocks
+    pub fn pending(&self) -> TryIter<'_, ControlRequest> {
+        self.receiver.try_iter()
+    }
+}
+
+/// Removes the socket a previous run may have left behind, refusing to remove any other file
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> std::io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn serve(reader: impl BufRead, mut writer: impl Write, sender: Sender<ControlRequest>) {
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_line(&line, &sender);
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+fn handle_line(line: &str, sender: &Sender<ControlRequest>) -> Value {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return error_response(Value::Null, PARSE_ERROR, &e.to_string()),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return error_response(id, INVALID_REQUEST, "Missing method");
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let command = match parse_command(method, &params) {
+        Ok(command) => command,
+        Err((code, message)) => return error_response(id, code, &message),
+    };
+
+    let (reply, result) = channel();
+    if sender.send(ControlRequest { command, reply }).is_err() {
+        return error_response(id, SERVER_ERROR, "Emulator is shutting down");
+    }
+    match result.recv() {
+        Ok(Ok(value)) => json!({"jsonrpc": "2.0", "id": id, "result": value}),
+        Ok(Err(message)) => error_response(id, SERVER_ERROR, &message),
+        Err(_) => error_response(id, SERVER_ERROR, "Emulator is shutting down"),
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn parse_command(method: &str, params: &Value) -> Result<ControlCommand, (i64, String)> {
+    let command = match method {
+        "pause" => ControlCommand::Pause,
+        "resume" => ControlCommand::Resume,
+        "step" => ControlCommand::Step,
+        "reset" => ControlCommand::Reset,
+        "read_memory" => ControlCommand::ReadMemory {
+            address: param_u64(params, "address")? as usize,
+            length: param_u64(params, "length")? as usize,
+        },
+        "write_memory" => ControlCommand::WriteMemory {
+            address: param_u64(params, "address")? as usize,
+            bytes: param_bytes(params, "bytes")?,
+        },
+        "read_registers" => ControlCommand::ReadRegisters,
+        "write_registers" => {
+            if !params.is_object() {
+                return Err(invalid_params("Expected an object of registers"));
+            }
+            ControlCommand::WriteRegisters(params.clone())
+        }
+        "press_key" => ControlCommand::PressKey(param_key(params)?),
+        "release_key" => ControlCommand::ReleaseKey(param_key(params)?),
+        "framebuffer" => ControlCommand::Framebuffer,
+        "load_rom" => ControlCommand::LoadRom(PathBuf::from(
+            params
+                .get("path")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid_params("Missing string parameter path"))?,
+        )),
+        _ => {
+            return Err((METHOD_NOT_FOUND, format!("Unknown method {}", method)));
+        }
+    };
+    Ok(command)
+}
+
+fn invalid_params(message: &str) -> (i64, String) {
+    (INVALID_PARAMS, message.to_string())
+}
+
+fn param_u64(params: &Value, name: &str) -> Result<u64, (i64, String)> {
+    params
+        .get(name)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| invalid_params(&format!("Missing integer parameter {}", name)))
+}
+
+fn param_key(params: &Value) -> Result<u8, (i64, String)> {
+    match param_u64(params, "key")? {
+        key @ 0..=0xF => Ok(key as u8),
+        key => Err(invalid_params(&format!("Invalid key {:#X}", key))),
+    }
+}
+
+fn param_bytes(params: &Value, name: &str) -> Result<Vec<u8>, (i64, String)> {
+    params
+        .get(name)
+        .and_then(Value::as_array)
+        .and_then(|values| {
+            values
+                .iter()
+                .map(|value| value.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect::<Option<Vec<u8>>>()
+        })
+        .ok_or_else(|| invalid_params(&format!("Missing byte array parameter {}", name)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_control_address() {
+        assert!(matches!(
+            "127.0.0.1:7878".parse(),
+            Ok(ControlAddress::Tcp(_))
+        ));
+        assert!(matches!("[::1]:7878".parse(), Ok(ControlAddress::Tcp(_))));
+        assert!("0.0.0.0:7878".parse::<ControlAddress>().is_err());
+        assert!("192.168.1.10:7878".parse::<ControlAddress>().is_err());
+        assert!("localhost".parse::<ControlAddress>().is_err());
+        #[cfg(unix)]
+        assert!(matches!(
+            "unix:/tmp/chip8.sock".parse(),
+            Ok(ControlAddress::Unix(_))
+        ));
+    }
+
+    fn error_code(response: &Value) -> Option<i64> {
+        response["error"]["code"].as_i64()
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert!(matches!(
+            parse_command("pause", &Value::Null),
+            Ok(ControlCommand::Pause)
+        ));
+        assert!(matches!(
+            parse_command("read_memory", &json!({"address": 512, "length": 4})),
+            Ok(ControlCommand::ReadMemory {
+                address: 512,
+                length: 4
+            })
+        ));
+        assert!(matches!(
+            parse_command("load_rom", &json!({"path": "pong.ch8"})),
+            Ok(ControlCommand::LoadRom(path)) if path == std::path::Path::new("pong.ch8")
+        ));
+        assert!(matches!(
+            parse_command("write_registers", &json!({"v0": 1})),
+            Ok(ControlCommand::WriteRegisters(_))
+        ));
+
+        let error = |method, params| parse_command(method, &params).unwrap_err().0;
+        assert_eq!(error("jump", Value::Null), METHOD_NOT_FOUND);
+        assert_eq!(
+            error("read_memory", json!({"address": 512})),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            error("read_memory", json!({"address": -1, "length": 1})),
+            INVALID_PARAMS
+        );
+        assert_eq!(error("write_registers", json!([1])), INVALID_PARAMS);
+        assert_eq!(error("load_rom", json!({"path": 1})), INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_param_key() {
+        assert_eq!(param_key(&json!({"key": 0})), Ok(0));
+        assert_eq!(param_key(&json!({"key": 15})), Ok(0xF));
+        assert_eq!(
+            param_key(&json!({"key": 16})).unwrap_err().0,
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            param_key(&json!({"key": "A"})).unwrap_err().0,
+            INVALID_PARAMS
+        );
+        assert_eq!(param_key(&json!({})).unwrap_err().0, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_param_bytes() {
+        assert_eq!(
+            param_bytes(&json!({"bytes": [0, 255]}), "bytes"),
+            Ok(vec![0, 255])
+        );
+        assert_eq!(param_bytes(&json!({"bytes": []}), "bytes"), Ok(vec![]));
+        for params in [
+            json!({"bytes": [256]}),
+            json!({"bytes": [-1]}),
+            json!({"bytes": ["00"]}),
+            json!({"bytes": 1}),
+            json!({}),
+        ] {
+            assert_eq!(param_bytes(&params, "bytes").unwrap_err().0, INVALID_PARAMS);
+        }
+    }
+
+    #[test]
+    fn test_handle_line_errors() {
+        let (sender, receiver) = channel();
+        let response = handle_line("{not json", &sender);
+        assert_eq!(error_code(&response), Some(PARSE_ERROR));
+        assert_eq!(response["id"], Value::Null);
+
+        let response = handle_line(r#"{"jsonrpc": "2.0", "id": 7}"#, &sender);
+        assert_eq!(error_code(&response), Some(INVALID_REQUEST));
+        assert_eq!(response["id"], 7);
+
+        let response = handle_line(r#"{"id": 1, "method": "jump"}"#, &sender);
+        assert_eq!(error_code(&response), Some(METHOD_NOT_FOUND));
+        let line = r#"{"id": 1, "method": "press_key", "params": {"key": 16}}"#;
+        assert_eq!(
+            error_code(&handle_line(line, &sender)),
+            Some(INVALID_PARAMS)
+        );
+        // Nothing reached the run loop
+        assert!(receiver.try_recv().is_err());
+
+        drop(receiver);
+        let response = handle_line(r#"{"id": 1, "method": "pause"}"#, &sender);
+        assert_eq!(error_code(&response), Some(SERVER_ERROR));
+    }
+
+    #[test]
+    fn test_handle_line_result() {
+        let (sender, receiver) = channel();
+        let emulator = thread::spawn(move || {
+            let request: ControlRequest = receiver.recv().unwrap();
+            assert!(matches!(request.command, ControlCommand::ReleaseKey(0xA)));
+            request.respond(Ok(json!("done")));
+            let request: ControlRequest = receiver.recv().unwrap();
+            request.respond(Err("No ROM".to_string()));
+        });
+        let line = r#"{"id": 3, "method": "release_key", "params": {"key": 10}}"#;
+        let response = handle_line(line, &sender);
+        assert_eq!(
+            response,
+            json!({"jsonrpc": "2.0", "id": 3, "result": "done"})
+        );
+        let response = handle_line(r#"{"id": 4, "method": "reset"}"#, &sender);
+        assert_eq!(error_code(&response), Some(SERVER_ERROR));
+        assert_eq!(response["error"]["message"], "No ROM");
+        emulator.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_remove_stale_socket_keeps_other_files() {
+        let path = std::env::temp_dir().join(format!("chip8-control-{}.ch8", std::process::id()));
+        std::fs::write(&path, [0x12, 0x00]).unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+        assert!(remove_stale_socket(&path).is_ok());
+
+        let listener = UnixListener::bind(&path).unwrap();
+        drop(listener);
+        assert!(remove_stale_socket(&path).is_ok());
+        assert!(!path.exists());
+    }
+}
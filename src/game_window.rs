@@ -1,16 +1,26 @@
+use crate::clock::EmulationRate;
 use crate::game_window::controls_writer::game_pad;
 use crate::game_window::timer_writer::write_timer;
+use crate::gamepad_mapping::GamepadMapping;
+use crate::key_mapping::KeyMapping;
+use crate::panel_layout::{Column, PanelKind, PanelLayout, column_rects};
+use crate::phosphor_filter::{FilterSettings, PhosphorFilter};
+use crate::rom_browser::RomBrowser;
 use crate::screen_config::ScreenConfig;
 use crate::state::State;
-use sdl3::Sdl;
+use crate::vip_timing::TimingMode;
+use clap::ValueEnum;
 use sdl3::pixels::Color;
 use sdl3::rect::Rect;
 use sdl3::render::{Canvas, FRect, ScaleMode, TextureCreator, create_renderer};
 use sdl3::surface::Surface;
 use sdl3::ttf::Font;
 use sdl3::video::{Window, WindowContext};
+use sdl3::{Sdl, VideoSubsystem};
 use std::cmp::max;
+use std::time::Duration;
 
+mod browser_writer;
 mod controls_writer;
 mod instruction_writer;
 mod registers_writer;
@@ -19,103 +29,175 @@ mod timer_writer;
 
 pub const HEADER_FONT_RATIO: f32 = 32.0;
 pub const TEXT_FONT_RATIO: f32 = 48.0;
+/// Below this size the panels cannot hold their text
+const MINIMUM_WINDOW_WIDTH: u32 = 640;
+const MINIMUM_WINDOW_HEIGHT: u32 = 360;
 
 struct ScreenManager {
     canvas: Canvas<Window>,
     texture_creator: TextureCreator<WindowContext>,
 }
 
-pub struct GameWindow<'a> {
-    controls_panel: Panel,
-    game_panel: Panel,
-    header_font: Font<'a>,
-    index_panel: Panel,
-    instructions_panel: Panel,
-    registers_panel: Panel,
-    screen_manager: ScreenManager,
-    stack_panel: Panel,
-    text_font: Font<'a>,
-    timer_panel: Panel,
+/// What the window shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DisplayMode {
+    /// The game with the instructions, registers, stack and controls panels
+    #[default]
+    Debugger,
+    /// Only the game, scaled to the whole window
+    Game,
+    /// Only the game, in borderless fullscreen
+    Fullscreen,
 }
 
-impl GameWindow<'_> {
-    pub fn new(sdl_context: &Sdl, screen_config: &ScreenConfig) -> Self {
-        let window_width = screen_config.window_dimensions.width as u32;
-        let window_height = screen_config.window_dimensions.height as u32;
+impl DisplayMode {
+    /// Next mode of the F11 cycle
+    pub fn next(self) -> Self {
+        match self {
+            DisplayMode::Debugger => DisplayMode::Game,
+            DisplayMode::Game => DisplayMode::Fullscreen,
+            DisplayMode::Fullscreen => DisplayMode::Debugger,
+        }
+    }
+}
 
-        let instructions_panel = Panel::new(
-            PanelType::Instructions,
-            0,
-            0,
-            window_width / 4,
-            window_height,
-        );
-        let game_panel = Panel::new(
-            PanelType::Game,
-            instructions_panel.boundaries.right(),
-            0,
-            window_width / 2,
-            window_height / 2,
-        );
-        let registers_panel = Panel::new(
-            PanelType::Registers,
-            game_panel.boundaries.right(),
-            0,
-            window_width / 4,
-            window_height,
-        );
-        let index_panel = Panel::new(
-            PanelType::Index,
-            game_panel.boundaries.right(),
-            0,
-            window_width / 4,
-            0,
-        );
-        let timer_panel = Panel::new(
-            PanelType::Timer,
-            game_panel.boundaries.right(),
-            0,
-            window_width / 4,
-            0,
-        );
-        let stack_panel = Panel::new(
-            PanelType::Stack,
-            game_panel.boundaries.right(),
-            0,
-            window_width / 4,
-            0,
-        );
-        let controls_panel = Panel::new(
-            PanelType::Controls,
-            instructions_panel.boundaries.right(),
-            game_panel.boundaries.bottom(),
-            game_panel.boundaries.width(),
-            game_panel.boundaries.height(),
-        );
+/// Speed of the emulation shown in the controls panel
+pub struct SpeedStatus {
+    pub rate: EmulationRate,
+    /// Instructions per second, unless with the VIP timing
+    pub speed: u32,
+    pub timing: TimingMode,
+    /// Frames drawn per second
+    pub fps: f64,
+    /// Key first pressed in the latest frame with input, and the delay until its drawing
+    pub input_lag: Option<(u8, Duration)>,
+}
 
-        let video_subsystem = sdl_context.video().unwrap();
+/// Everything the panels show, for one frame
+pub struct PanelContent<'c> {
+    pub state: &'c State,
+    pub screen_config: &'c ScreenConfig,
+    pub key_mapping: &'c KeyMapping,
+    pub gamepad_mapping: Option<&'c GamepadMapping>,
+    /// The ROM browser replaces the column of the game when open
+    pub browser: Option<&'c RomBrowser>,
+    pub speed_status: &'c SpeedStatus,
+}
+
+struct Fonts<'a> {
+    header: Font<'a>,
+    text: Font<'a>,
+}
 
-        let window = video_subsystem
-            .window("chip8", window_width, window_height)
+/// What the game panel draws and how tall it is
+struct GamePanel<'p> {
+    /// Fraction of the height of the column
+    height: f32,
+    pixels: &'p [u8],
+}
+
+/// A window with its columns of panels
+struct PanelWindow {
+    screen_manager: ScreenManager,
+    columns: Vec<Column>,
+    column_rects: Vec<Rect>,
+}
+
+impl PanelWindow {
+    fn open(
+        video_subsystem: &VideoSubsystem,
+        title: &str,
+        width: u32,
+        height: u32,
+        columns: Vec<Column>,
+        screen_config: &ScreenConfig,
+    ) -> Self {
+        let mut window = video_subsystem
+            .window(title, width, height)
             .position_centered()
+            .resizable()
             .build()
             .unwrap();
+        window
+            .set_minimum_size(MINIMUM_WINDOW_WIDTH, MINIMUM_WINDOW_HEIGHT)
+            .unwrap();
         let mut canvas = create_renderer(window, None).unwrap();
         canvas.set_draw_color(screen_config.colors.off_color);
         canvas.clear();
         canvas.present();
 
         let texture_creator = canvas.texture_creator();
-
-        let screen_manager = ScreenManager {
-            canvas,
-            texture_creator,
+        let mut panel_window = Self {
+            screen_manager: ScreenManager {
+                canvas,
+                texture_creator,
+            },
+            columns,
+            column_rects: Vec::new(),
         };
+        panel_window.resize(width, height);
+        panel_window
+    }
+
+    fn id(&self) -> u32 {
+        self.screen_manager.canvas.window().id()
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.column_rects = column_rects(&self.columns, width, height);
+    }
+}
+
+pub struct GameWindow<'a> {
+    /// Second window holding the detached panels, if any
+    detached: Option<PanelWindow>,
+    display_mode: DisplayMode,
+    /// Blends the last frames of the game before they are drawn
+    filter: PhosphorFilter,
+    fonts: Fonts<'a>,
+    /// Height of the game panel, as a fraction of the height of the window
+    game_height: f32,
+    /// Where every key of the on-screen keypad was last drawn, to find the clicked ones
+    keypad_rects: [Option<Rect>; 16],
+    /// Window the on-screen keypad was last drawn in
+    keypad_window: Option<u32>,
+    main: PanelWindow,
+}
+
+impl GameWindow<'_> {
+    pub fn new(
+        sdl_context: &Sdl,
+        screen_config: &ScreenConfig,
+        display_mode: DisplayMode,
+        layout: &PanelLayout,
+        filter: &FilterSettings,
+    ) -> Self {
+        let window_width = screen_config.window_dimensions.width as u32;
+        let window_height = screen_config.window_dimensions.height as u32;
+
+        let video_subsystem = sdl_context.video().unwrap();
+        let main = PanelWindow::open(
+            &video_subsystem,
+            "chip8",
+            window_width,
+            window_height,
+            layout.columns.clone(),
+            screen_config,
+        );
+        let detached = (!layout.detached.is_empty()).then(|| {
+            PanelWindow::open(
+                &video_subsystem,
+                "chip8 debugger",
+                window_width / 2,
+                window_height,
+                layout.detached.clone(),
+                screen_config,
+            )
+        });
 
         let ttf_context = sdl3::ttf::init().unwrap();
 
-        let header_font_size = screen_config.window_dimensions.width as f32 / HEADER_FONT_RATIO;
-        let text_font_size = screen_config.window_dimensions.width as f32 / TEXT_FONT_RATIO;
+        let (header_font_size, text_font_size) = font_sizes(window_width);
         let header_font = ttf_context
             .load_font("assets/Zolofont.ttf", header_font_size)
             .unwrap();
@@ -123,191 +205,398 @@ impl GameWindow<'_> {
             .load_font("assets/Zolofont.ttf", text_font_size)
             .unwrap();
 
-        Self {
-            screen_manager,
-            header_font,
-            text_font,
-            game_panel,
-            instructions_panel,
-            registers_panel,
-            index_panel,
-            timer_panel,
-            stack_panel,
-            controls_panel,
+        let mut game_window = Self {
+            detached,
+            display_mode: DisplayMode::Debugger,
+            filter: PhosphorFilter::new(filter),
+            fonts: Fonts {
+                header: header_font,
+                text: text_font,
+            },
+            game_height: layout.game_height,
+            keypad_rects: [None; 16],
+            keypad_window: None,
+            main,
+        };
+        game_window.set_display_mode(display_mode);
+        game_window
+    }
+
+    pub fn display_mode(&self) -> DisplayMode {
+        self.display_mode
+    }
+
+    /// Switches between the debugger, the game alone and the game in fullscreen
+    pub fn set_display_mode(&mut self, display_mode: DisplayMode) {
+        self.display_mode = display_mode;
+        let window = self.main.screen_manager.canvas.window_mut();
+        if let Err(e) = window.set_fullscreen(display_mode == DisplayMode::Fullscreen) {
+            eprintln!("Could not switch fullscreen : {}", e);
         }
+        let (width, height) = window.size();
+        let id = self.main.id();
+        self.resize(id, width, height);
     }
 
-    pub fn update(&mut self, state: &State, screen_config: &ScreenConfig) {
-        self.screen_manager
-            .canvas
-            .set_draw_color(screen_config.colors.off_color);
-
-        self.screen_manager.canvas.clear();
-        self.draw_controls(state, screen_config);
-        self.draw_instructions(state, screen_config);
-        self.draw_registers(state, screen_config);
-        self.draw_index(state, screen_config);
-        self.draw_timer(state, screen_config);
-        self.draw_stack(state, screen_config);
-        self.draw_layout(screen_config);
-        self.update_game_screen(state, screen_config);
-        self.screen_manager.canvas.present();
+    /// Lays the panels of a window out again after it was resized, the fonts following the
+    /// size of the main window
+    pub fn resize(&mut self, window_id: u32, width: u32, height: u32) {
+        if window_id == self.main.id() {
+            self.main.resize(width, height);
+            let (header_font_size, text_font_size) = font_sizes(width);
+            self.fonts.header.set_size(header_font_size).unwrap();
+            self.fonts.text.set_size(text_font_size).unwrap();
+        } else if let Some(detached) = self.detached.as_mut().filter(|w| w.id() == window_id) {
+            detached.resize(width, height);
+        }
     }
 
-    fn draw_controls(&mut self, state: &State, screen_config: &ScreenConfig) {
-        let remaining_rect = self.write_header(self.controls_panel.clone(), screen_config);
-        let drawn_rect = self.screen_manager.write_text(
-            controls_writer::write_fn_controls(),
-            &self.text_font,
-            remaining_rect,
-            screen_config,
-        );
-        let remaining_rect = subtract_rect(remaining_rect, drawn_rect, Direction::Up);
-        let remaining = self.draw_pressed_keys(state, remaining_rect, screen_config);
-        self.screen_manager.write_text(
-            controls_writer::write_game_controls(),
-            &self.text_font,
-            remaining,
-            screen_config,
-        );
+    /// Closes the detached panels when their window is closed, returns whether it was theirs
+    pub fn close(&mut self, window_id: u32) -> bool {
+        let detached = self.detached.as_ref().is_some_and(|w| w.id() == window_id);
+        if detached {
+            self.detached = None;
+        }
+        detached
     }
 
-    fn draw_instructions(&mut self, state: &State, screen_config: &ScreenConfig) {
-        let remaining_rect = self.write_header(self.instructions_panel.clone(), screen_config);
-        self.screen_manager.write_text(
-            &instruction_writer::write_instructions(state),
-            &self.text_font,
-            remaining_rect,
-            screen_config,
-        );
+    /// Key of the on-screen keypad at a position of a window, if any
+    pub fn keypad_key_at(&self, window_id: u32, x: f32, y: f32) -> Option<u8> {
+        if self.keypad_window != Some(window_id) {
+            return None;
+        }
+        let point = sdl3::rect::Point::new(x as i32, y as i32);
+        self.keypad_rects
+            .iter()
+            .position(|rect| rect.is_some_and(|rect| rect.contains_point(point)))
+            .map(|key| key as u8)
     }
 
-    fn draw_registers(&mut self, state: &State, screen_config: &ScreenConfig) {
-        let remaining_rect = self.write_header(self.registers_panel.clone(), screen_config);
-        let drawn_rect = self.screen_manager.write_text(
-            &registers_writer::write_registers(state),
-            &self.text_font,
-            remaining_rect,
-            screen_config,
-        );
-        self.index_panel.boundaries =
-            subtract_rect(self.registers_panel.boundaries, drawn_rect, Direction::Up);
+    /// Converts the normalized position of a touch event into coordinates of the window it
+    /// happened in, the main one when it is unknown, returned with its id
+    pub fn touch_position(&self, window_id: Option<u32>, x: f32, y: f32) -> (u32, f32, f32) {
+        let window = self
+            .detached
+            .as_ref()
+            .filter(|w| Some(w.id()) == window_id)
+            .unwrap_or(&self.main);
+        let (width, height) = window.screen_manager.canvas.window().size();
+        (window.id(), x * width as f32, y * height as f32)
     }
 
-    fn draw_layout(&mut self, screen_config: &ScreenConfig) {
-        self.screen_manager
-            .canvas
-            .set_draw_color(screen_config.colors.on_color);
-        self.screen_manager
-            .canvas
-            .draw_rects(&[
-                FRect::from(self.instructions_panel.boundaries),
-                FRect::from(self.registers_panel.boundaries),
-                FRect::from(self.controls_panel.boundaries),
-                FRect::from(self.index_panel.boundaries),
-                FRect::from(self.stack_panel.boundaries),
-            ])
-            .unwrap();
+    /// Draws the machine state in every window
+    pub fn update(&mut self, content: &PanelContent) {
+        self.keypad_rects = [None; 16];
+        self.keypad_window = None;
+        let colors = &content.screen_config.colors;
+        // Filtered once per frame, whichever window shows the game
+        let game = GamePanel {
+            height: self.game_height,
+            pixels: self
+                .filter
+                .apply(&content.state.screen, content.screen_config),
+        };
+
+        let screen_manager = &mut self.main.screen_manager;
+        screen_manager.canvas.set_draw_color(colors.off_color);
+        screen_manager.canvas.clear();
+        if self.display_mode == DisplayMode::Debugger {
+            let keypad = screen_manager.draw_columns(
+                &self.main.columns,
+                &self.main.column_rects,
+                &game,
+                &self.fonts,
+                content,
+                &mut self.keypad_rects,
+            );
+            if keypad {
+                self.keypad_window = Some(self.main.id());
+            }
+        } else {
+            // Without the debugger the game and the ROM browser take the whole window
+            let (width, height) = screen_manager.canvas.window().size();
+            let window = Rect::new(0, 0, width, height);
+            match content.browser {
+                Some(browser) => screen_manager.draw_browser(browser, window, &self.fonts, content),
+                None => screen_manager.draw_game(window, game.pixels, content, letterboxed),
+            }
+        }
+        self.main.screen_manager.canvas.present();
+
+        if let Some(detached) = self.detached.as_mut() {
+            let screen_manager = &mut detached.screen_manager;
+            screen_manager.canvas.set_draw_color(colors.off_color);
+            screen_manager.canvas.clear();
+            let keypad = screen_manager.draw_columns(
+                &detached.columns,
+                &detached.column_rects,
+                &game,
+                &self.fonts,
+                content,
+                &mut self.keypad_rects,
+            );
+            if keypad {
+                self.keypad_window = Some(detached.id());
+            }
+            detached.screen_manager.canvas.present();
+        }
+    }
+}
+
+impl ScreenManager {
+    /// Draws the panels of every column, returns whether the on-screen keypad was drawn
+    fn draw_columns(
+        &mut self,
+        columns: &[Column],
+        column_rects: &[Rect],
+        game: &GamePanel,
+        fonts: &Fonts,
+        content: &PanelContent,
+        keypad_rects: &mut [Option<Rect>; 16],
+    ) -> bool {
+        let mut keypad = false;
+        for (column, rect) in columns.iter().zip(column_rects) {
+            if let Some(browser) = content.browser
+                && column.panels.contains(&PanelKind::Game)
+            {
+                self.draw_browser(browser, *rect, fonts, content);
+                continue;
+            }
+            let mut remaining = *rect;
+            for (index, panel) in column.panels.iter().enumerate() {
+                let last = index + 1 == column.panels.len();
+                let used = match panel {
+                    PanelKind::Game => {
+                        let height = (rect.height() as f32 * game.height) as u32;
+                        let area = Rect::new(
+                            remaining.x(),
+                            remaining.y(),
+                            remaining.width(),
+                            height.min(remaining.height()),
+                        );
+                        self.draw_game(area, game.pixels, content, |area, width, height| {
+                            FRect::from(integer_scaled(area, width, height))
+                        });
+                        area
+                    }
+                    panel => {
+                        keypad |= *panel == PanelKind::Controls;
+                        let bottom =
+                            self.draw_panel(*panel, remaining, fonts, content, keypad_rects);
+                        let used = match last {
+                            true => remaining,
+                            false => Rect::new(
+                                remaining.x(),
+                                remaining.y(),
+                                remaining.width(),
+                                (bottom - remaining.y()).max(0) as u32,
+                            ),
+                        };
+                        self.canvas
+                            .set_draw_color(content.screen_config.colors.on_color);
+                        self.canvas.draw_rect(FRect::from(used)).unwrap();
+                        used
+                    }
+                };
+                remaining = subtract_rect(remaining, used, Direction::Up);
+            }
+        }
+        keypad
+    }
+
+    /// Draws a panel with its header from the top of `rect`, returns the bottom of its content
+    fn draw_panel(
+        &mut self,
+        panel: PanelKind,
+        rect: Rect,
+        fonts: &Fonts,
+        content: &PanelContent,
+        keypad_rects: &mut [Option<Rect>; 16],
+    ) -> i32 {
+        let remaining = self.write_header(header(panel), rect, fonts, content.screen_config);
+        let state = content.state;
+        let text = match panel {
+            PanelKind::Controls => {
+                return self.draw_controls(remaining, fonts, content, keypad_rects);
+            }
+            PanelKind::Instructions => instruction_writer::write_instructions(state),
+            PanelKind::Registers => registers_writer::write_registers(state),
+            PanelKind::Index => write_index(state),
+            PanelKind::Timers => write_timer(state),
+            PanelKind::Stack => stack_writer::write_stack(state),
+            PanelKind::Game => unreachable!("The game has no text"),
+        };
+        let drawn = self.write_text(&text, &fonts.text, remaining, content.screen_config);
+        drawn.bottom()
     }
 
-    fn update_game_screen(&mut self, state: &State, screen_config: &ScreenConfig) {
+    /// Draws the pixels of the game, filtered or not, scaled into `area`
+    fn draw_game(
+        &mut self,
+        area: Rect,
+        pixels: &[u8],
+        content: &PanelContent,
+        scale: impl Fn(Rect, u32, u32) -> FRect,
+    ) {
+        let screen_config = content.screen_config;
+        let (width, height) = (
+            screen_config.screen_dimensions.width as u32,
+            screen_config.screen_dimensions.height as u32,
+        );
         let mut texture = self
-            .screen_manager
             .texture_creator
-            .create_texture_target(
-                screen_config.pixel_format,
-                screen_config.screen_dimensions.width as u32,
-                screen_config.screen_dimensions.height as u32,
-            )
-            .unwrap();
-        texture
-            .update(None, &state.screen.pixels, screen_config.pitch())
+            .create_texture_target(screen_config.pixel_format, width, height)
             .unwrap();
+        texture.update(None, pixels, screen_config.pitch()).unwrap();
         texture.set_scale_mode(ScaleMode::Nearest);
-        self.screen_manager
-            .canvas
-            .copy(&texture, None, self.game_panel.boundaries)
+        self.canvas
+            .copy(&texture, None, scale(area, width, height))
             .unwrap();
     }
 
-    fn write_header(&mut self, panel: Panel, screen_config: &ScreenConfig) -> Rect {
-        let mut drawn_rect = self.screen_manager.write_text(
-            &panel.header,
-            &self.header_font,
-            panel.boundaries,
+    fn draw_browser(
+        &mut self,
+        browser: &RomBrowser,
+        rect: Rect,
+        fonts: &Fonts,
+        content: &PanelContent,
+    ) {
+        let screen_config = content.screen_config;
+        let remaining_rect = self.write_header(BROWSER_HEADER, rect, fonts, screen_config);
+        let drawn_rect = self.write_text(
+            &browser_writer::write_folder(browser),
+            &fonts.text,
+            remaining_rect,
             screen_config,
         );
-        drawn_rect.set_x(panel.boundaries.left());
-        drawn_rect.set_width(panel.boundaries.width());
-        self.screen_manager
-            .canvas
-            .set_draw_color(screen_config.colors.on_color);
-        self.screen_manager
-            .canvas
-            .draw_rect(FRect::from(drawn_rect))
-            .unwrap();
-        subtract_rect(panel.boundaries, drawn_rect, Direction::Up)
-    }
-
-    fn draw_index(&mut self, state: &State, screen_config: &ScreenConfig) {
-        let remaining_rect = self.write_header(self.index_panel.clone(), screen_config);
-        let drawn_rect = self.screen_manager.write_text(
-            &write_index(state),
-            &self.text_font,
+        let mut remaining_rect = subtract_rect(remaining_rect, drawn_rect, Direction::Up);
+        let drawn_rect = self.write_text(
+            browser_writer::write_browser_controls(),
+            &fonts.text,
             remaining_rect,
             screen_config,
         );
-        self.timer_panel.boundaries =
-            subtract_rect(self.index_panel.boundaries, drawn_rect, Direction::Up);
+        remaining_rect = subtract_rect(remaining_rect, drawn_rect, Direction::Up);
+        let rows = remaining_rect.height() as usize / max(fonts.text.height(), 1) as usize;
+        let (first, entries) = browser.visible_entries(rows);
+        for (index, entry) in (first..).zip(entries) {
+            let color = if index == browser.selected {
+                screen_config.colors.alt_color
+            } else {
+                screen_config.colors.on_color
+            };
+            let drawn = self.write_text_color(
+                &browser_writer::write_entry(entry),
+                &fonts.text,
+                remaining_rect,
+                color,
+                screen_config.colors.off_color,
+                screen_config,
+            );
+            remaining_rect = subtract_rect(remaining_rect, drawn, Direction::Up);
+        }
+        self.canvas.set_draw_color(screen_config.colors.on_color);
+        self.canvas.draw_rect(FRect::from(rect)).unwrap();
     }
 
-    fn draw_timer(&mut self, state: &State, screen_config: &ScreenConfig) {
-        let remaining_rect = self.write_header(self.timer_panel.clone(), screen_config);
-        let drawn_rect = self.screen_manager.write_text(
-            &write_timer(state),
-            &self.text_font,
-            remaining_rect,
+    /// Draws the function keys, the keypad and the key bindings, returns the bottom of them
+    fn draw_controls(
+        &mut self,
+        rect: Rect,
+        fonts: &Fonts,
+        content: &PanelContent,
+        keypad_rects: &mut [Option<Rect>; 16],
+    ) -> i32 {
+        let screen_config = content.screen_config;
+        let drawn_rect = self.write_text(
+            &controls_writer::write_fn_controls(content.speed_status),
+            &fonts.text,
+            rect,
             screen_config,
         );
-        self.stack_panel.boundaries =
-            subtract_rect(self.timer_panel.boundaries, drawn_rect, Direction::Up);
-    }
-    fn draw_stack(&mut self, state: &State, screen_config: &ScreenConfig) {
-        let remaining_rect = self.write_header(self.stack_panel.clone(), screen_config);
-        self.screen_manager.write_text(
-            &stack_writer::write_stack(state),
-            &self.text_font,
+        let remaining_rect = subtract_rect(rect, drawn_rect, Direction::Up);
+        let (remaining, keypad_bottom) = self.draw_pressed_keys(
+            content.state,
             remaining_rect,
+            fonts,
             screen_config,
+            keypad_rects,
         );
+        let drawn_rect = self.write_text(
+            &controls_writer::write_game_controls(content.key_mapping),
+            &fonts.text,
+            remaining,
+            screen_config,
+        );
+        let mut bottom = keypad_bottom.max(drawn_rect.bottom());
+        if let Some(gamepad_mapping) = content.gamepad_mapping {
+            let drawn = self.write_text(
+                &controls_writer::write_gamepad_controls(gamepad_mapping),
+                &fonts.text,
+                subtract_rect(remaining, drawn_rect, Direction::Up),
+                screen_config,
+            );
+            bottom = bottom.max(drawn.bottom());
+        }
+        bottom
+    }
+
+    /// Writes the header of a panel at the top of `rect`, returns the rest of `rect`
+    fn write_header(
+        &mut self,
+        header: &str,
+        rect: Rect,
+        fonts: &Fonts,
+        screen_config: &ScreenConfig,
+    ) -> Rect {
+        let mut drawn_rect = self.write_text(header, &fonts.header, rect, screen_config);
+        drawn_rect.set_x(rect.left());
+        drawn_rect.set_width(rect.width());
+        self.canvas.set_draw_color(screen_config.colors.on_color);
+        self.canvas.draw_rect(FRect::from(drawn_rect)).unwrap();
+        subtract_rect(rect, drawn_rect, Direction::Up)
     }
 
+    /// Draws the keypad with the pressed keys highlighted, returns the rest of `rect` on the
+    /// right of the keypad and the bottom of the keypad
     fn draw_pressed_keys(
         &mut self,
         state: &State,
         rect: Rect,
+        fonts: &Fonts,
         screen_config: &ScreenConfig,
-    ) -> Rect {
-        let drawn = self.screen_manager.write_text(
+        keypad_rects: &mut [Option<Rect>; 16],
+    ) -> (Rect, i32) {
+        let drawn = self.write_text(
             controls_writer::write_hyphen_line(),
-            &self.text_font,
+            &fonts.text,
             rect,
             screen_config,
         );
         let mut remaining_rect = subtract_rect(rect, drawn, Direction::Up);
         let mut drawn = Rect::new(rect.x, rect.y, 0, 0);
         for line in game_pad() {
-            let drawn_rect = self.draw_line(line, state, remaining_rect, screen_config);
+            let drawn_rect = self.draw_line(
+                line,
+                state,
+                remaining_rect,
+                fonts,
+                screen_config,
+                keypad_rects,
+            );
             drawn = add_rect(drawn_rect, drawn, Direction::Up);
             remaining_rect = subtract_rect(remaining_rect, drawn_rect, Direction::Up);
         }
-        self.screen_manager.write_text(
+        let bottom_line = self.write_text(
             controls_writer::write_hyphen_line(),
-            &self.text_font,
+            &fonts.text,
             remaining_rect,
             screen_config,
         );
-        subtract_rect(rect, drawn, Direction::Left)
+        (
+            subtract_rect(rect, drawn, Direction::Left),
+            bottom_line.bottom(),
+        )
     }
 
     fn draw_line(
@@ -315,33 +604,120 @@ impl GameWindow<'_> {
         line: [u8; 4],
         state: &State,
         rect: Rect,
+        fonts: &Fonts,
         screen_config: &ScreenConfig,
+        keypad_rects: &mut [Option<Rect>; 16],
     ) -> Rect {
-        let mut drawn_rect =
-            self.screen_manager
-                .write_text("|", &self.text_font, rect, screen_config);
+        let mut drawn_rect = self.write_text("|", &fonts.text, rect, screen_config);
         let mut remaining_rect = subtract_rect(rect, drawn_rect, Direction::Left);
+        // Keys tapped within the frame are highlighted too, so that short presses show
+        let pressed = state.keypad.bits() | state.keypad.pressed_this_frame();
         for key in line {
-            let color = if state.keypad.is_pressed(key) {
+            let color = if pressed & 1 << key != 0 {
                 screen_config.colors.alt_color
             } else {
                 screen_config.colors.on_color
             };
-            let drawn = self.screen_manager.write_text_color(
+            let drawn = self.write_text_color(
                 &format!("{:X}", key),
-                &self.text_font,
+                &fonts.text,
                 remaining_rect,
                 color,
                 screen_config.colors.off_color,
                 screen_config,
             );
+            // The margins around the key are part of it, so the whole grid is clickable
+            let margin = screen_config.margin() as i32;
+            keypad_rects[key as usize] = Some(Rect::new(
+                drawn.x() - margin,
+                drawn.y(),
+                drawn.width() + 2 * margin as u32,
+                drawn.height(),
+            ));
             remaining_rect = subtract_rect(remaining_rect, drawn, Direction::Left);
             drawn_rect = add_rect(drawn, drawn_rect, Direction::Right);
         }
-        self.screen_manager
-            .write_text("|", &self.text_font, remaining_rect, screen_config);
+        self.write_text("|", &fonts.text, remaining_rect, screen_config);
         drawn_rect
     }
+
+    fn write_text(
+        &mut self,
+        text: &str,
+        font: &Font,
+        dst: Rect,
+        screen_config: &ScreenConfig,
+    ) -> Rect {
+        self.write_text_color(
+            text,
+            font,
+            dst,
+            screen_config.colors.on_color,
+            screen_config.colors.off_color,
+            screen_config,
+        )
+    }
+    fn write_text_color(
+        &mut self,
+        text: &str,
+        font: &Font,
+        dst: Rect,
+        on_color: Color,
+        off_color: Color,
+        screen_config: &ScreenConfig,
+    ) -> Rect {
+        let mut draw_rect = self.apply_lateral_margin(&dst, screen_config);
+        let rendered_text = render_text(font, on_color, off_color, text, draw_rect.width() as i32);
+        draw_text(
+            rendered_text,
+            &mut draw_rect,
+            &self.texture_creator,
+            &mut self.canvas,
+        )
+    }
+    fn apply_lateral_margin(&self, rect: &Rect, screen_config: &ScreenConfig) -> Rect {
+        let size = screen_config.margin();
+        Rect::new(
+            rect.x() + size as i32,
+            rect.y(),
+            rect.width().saturating_sub(2 * size as u32),
+            rect.height(),
+        )
+    }
+}
+
+/// Sizes of the header and text fonts, proportional to the width of the window
+fn font_sizes(window_width: u32) -> (f32, f32) {
+    (
+        window_width as f32 / HEADER_FONT_RATIO,
+        window_width as f32 / TEXT_FONT_RATIO,
+    )
+}
+
+/// Largest rectangle of whole multiples of `width`×`height` centered in `area`, so that the
+/// pixels of the game all have the same size
+fn integer_scaled(area: Rect, width: u32, height: u32) -> Rect {
+    let scale = (area.width() / width).min(area.height() / height).max(1);
+    let (scaled_width, scaled_height) = (width * scale, height * scale);
+    Rect::new(
+        area.x() + (area.width() as i32 - scaled_width as i32) / 2,
+        area.y() + (area.height() as i32 - scaled_height as i32) / 2,
+        scaled_width,
+        scaled_height,
+    )
+}
+
+/// Largest rectangle of the aspect ratio of `width`×`height` centered in `area`, the rest
+/// of the area being left as borders
+fn letterboxed(area: Rect, width: u32, height: u32) -> FRect {
+    let scale = (area.width() as f32 / width as f32).min(area.height() as f32 / height as f32);
+    let (scaled_width, scaled_height) = (width as f32 * scale, height as f32 * scale);
+    FRect::new(
+        area.x() as f32 + (area.width() as f32 - scaled_width) / 2.0,
+        area.y() as f32 + (area.height() as f32 - scaled_height) / 2.0,
+        scaled_width,
+        scaled_height,
+    )
 }
 
 fn write_index(state: &State) -> String {
@@ -437,89 +813,51 @@ enum Direction {
     Right,
 }
 
-impl ScreenManager {
-    fn write_text(
-        &mut self,
-        text: &str,
-        font: &Font,
-        dst: Rect,
-        screen_config: &ScreenConfig,
-    ) -> Rect {
-        self.write_text_color(
-            text,
-            font,
-            dst,
-            screen_config.colors.on_color,
-            screen_config.colors.off_color,
-            screen_config,
-        )
-    }
-    fn write_text_color(
-        &mut self,
-        text: &str,
-        font: &Font,
-        dst: Rect,
-        on_color: Color,
-        off_color: Color,
-        screen_config: &ScreenConfig,
-    ) -> Rect {
-        let mut draw_rect = self.apply_lateral_margin(&dst, screen_config);
-        let rendered_text = render_text(font, on_color, off_color, text, draw_rect.width() as i32);
-        draw_text(
-            rendered_text,
-            &mut draw_rect,
-            &self.texture_creator,
-            &mut self.canvas,
-        )
-    }
-    fn apply_lateral_margin(&self, rect: &Rect, screen_config: &ScreenConfig) -> Rect {
-        let size = screen_config.margin();
-        Rect::new(
-            rect.x() + size as i32,
-            rect.y(),
-            rect.width() - (2 * size as u32),
-            rect.height(),
-        )
-    }
-}
-
 const REGISTERS_HEADER: &str = "REGISTERS";
 const CONTROLS_HEADER: &str = "CONTROLS";
 const INSTRUCTIONS_HEADER: &str = "INSTRUCTIONS";
 const INDEX_HEADER: &str = "INDEX";
 const STACK_HEADER: &str = "STACK";
 const TIMER_HEADER: &str = "TIMERS";
-
-enum PanelType {
-    Instructions,
-    Registers,
-    Controls,
-    Game,
-    Index,
-    Stack,
-    Timer,
+const BROWSER_HEADER: &str = "ROMS";
+
+fn header(panel: PanelKind) -> &'static str {
+    match panel {
+        PanelKind::Instructions => INSTRUCTIONS_HEADER,
+        PanelKind::Registers => REGISTERS_HEADER,
+        PanelKind::Controls => CONTROLS_HEADER,
+        PanelKind::Index => INDEX_HEADER,
+        PanelKind::Stack => STACK_HEADER,
+        PanelKind::Timers => TIMER_HEADER,
+        PanelKind::Game => "",
+    }
 }
 
-#[derive(Clone)]
-struct Panel {
-    boundaries: Rect,
-    header: String,
-}
+#[cfg(test)]
+mod test {
+    use super::*;
 
-impl Panel {
-    fn new(panel_type: PanelType, x: i32, y: i32, width: u32, height: u32) -> Self {
-        let header = match panel_type {
-            PanelType::Instructions => INSTRUCTIONS_HEADER.to_string(),
-            PanelType::Registers => REGISTERS_HEADER.to_string(),
-            PanelType::Controls => CONTROLS_HEADER.to_string(),
-            PanelType::Index => INDEX_HEADER.to_string(),
-            PanelType::Stack => STACK_HEADER.to_string(),
-            PanelType::Timer => TIMER_HEADER.to_string(),
-            PanelType::Game => "".to_string(),
-        };
-        Self {
-            boundaries: Rect::new(x, y, width, height),
-            header,
-        }
+    #[test]
+    fn test_integer_scaled() {
+        assert_eq!(
+            integer_scaled(Rect::new(480, 0, 960, 540), 64, 32),
+            Rect::new(480, 30, 960, 480)
+        );
+        assert_eq!(
+            integer_scaled(Rect::new(0, 0, 700, 300), 64, 32),
+            Rect::new(62, 6, 576, 288)
+        );
+    }
+
+    #[test]
+    fn test_letterboxed() {
+        assert_eq!(
+            letterboxed(Rect::new(0, 0, 1920, 1080), 64, 32),
+            FRect::new(0.0, 60.0, 1920.0, 960.0)
+        );
+        assert_eq!(
+            letterboxed(Rect::new(0, 0, 1000, 1000), 64, 32),
+            FRect::new(0.0, 250.0, 1000.0, 500.0)
+        );
     }
 }
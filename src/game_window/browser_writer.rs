@@ -0,0 +1,32 @@
+use crate::rom_browser::{BrowserEntry, EntryKind, RomBrowser};
+
+const NAME_WIDTH: usize = 24;
+
+pub(crate) fn write_browser_controls<'b>() -> &'b str {
+    "UP/DOWN: SELECT | ENTER: OPEN | BACKSPACE: PARENT | ESC: CLOSE\nDROP A ROM ON THE WINDOW TO OPEN IT, * MARKS RECENT ROMS"
+}
+
+pub fn write_folder(browser: &RomBrowser) -> String {
+    browser.folder.display().to_string()
+}
+
+pub fn write_entry(entry: &BrowserEntry) -> String {
+    let mut name = entry.name();
+    if name.chars().count() > NAME_WIDTH {
+        name = name.chars().take(NAME_WIDTH - 1).collect::<String>() + "~";
+    }
+    match &entry.kind {
+        EntryKind::Parent | EntryKind::Folder => format!(" {}/", name),
+        EntryKind::Rom { size, variant } => {
+            let marker = if entry.recent { "*" } else { " " };
+            format!(
+                "{}{:<width$} {:>6} B  {}",
+                marker,
+                name,
+                size,
+                variant,
+                width = NAME_WIDTH
+            )
+        }
+    }
+}
@@ -1,25 +1,73 @@
-const LAYOUT: [[u8; 4]; 4] = [
-    [0x1, 0x2, 0x3, 0xC],
-    [0x4, 0x5, 0x6, 0xD],
-    [0x7, 0x8, 0x9, 0xE],
-    [0xA, 0x0, 0xB, 0xF],
-];
+use crate::game_window::SpeedStatus;
+use crate::gamepad_mapping::GamepadMapping;
+use crate::key_mapping::KeyMapping;
+use crate::keypad::KEYPAD_LAYOUT;
+use crate::vip_timing::TimingMode;
+
 const HYPHEN_LINE: &str = "----------";
-pub(crate) fn write_fn_controls<'f>() -> &'f str {
-    "    F1: PLAY/PAUSE | F2: STEP | F3: RESET | F4: EXIT"
+pub(crate) fn write_fn_controls(status: &SpeedStatus) -> String {
+    // The speed is set by the machine cycles with the VIP timing
+    let speed = match status.timing {
+        TimingMode::Instructions => format!(
+            "F9/F10: {} HZ ({:.1} IPF) | F12: SAVE",
+            status.speed,
+            status.speed as f64 / 60.0
+        ),
+        TimingMode::VipCycles => "COSMAC VIP TIMING".to_string(),
+    };
+    let input_lag = status
+        .input_lag
+        .map(|(key, lag)| format!(" | KEY {:X} LAG {} MS", key, lag.as_millis()))
+        .unwrap_or_default();
+    format!(
+        "    F1: PLAY/PAUSE | F2: STEP | F3: RESET | F4: EXIT | F5: ROMS\n    F6/F7: SLOWER/FASTER ({}) | F8: NEXT FRAME\n    {} | {:.0} FPS{}",
+        status.rate.label(),
+        speed,
+        status.fps,
+        input_lag
+    )
+}
+
+/// Keyboard keys of the keypad, laid out like the keypad
+pub(crate) fn write_game_controls(key_mapping: &KeyMapping) -> String {
+    let width = KEYPAD_LAYOUT
+        .iter()
+        .flatten()
+        .map(|key| key_mapping.label(*key).chars().count())
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let rows: Vec<String> = KEYPAD_LAYOUT
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|key| format!("{:^width$}", key_mapping.label(*key), width = width))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    let border = "-".repeat(rows[0].chars().count() + 4);
+    let mut lines = vec![format!("   {}", border)];
+    for (i, row) in rows.iter().enumerate() {
+        let prefix = if i == 1 || i == 2 { "_" } else { " " };
+        lines.push(format!("{}  | {} |", prefix, row));
+    }
+    lines.push(format!("   {}", border));
+    lines.join("\n")
 }
 
-pub(crate) fn write_game_controls<'g>() -> &'g str {
-    "   -----------
-   | 1 2 3 4 |
-_  | Q W E R |
-_  | A S D F |
-   | Z X C V |
-   -----------"
+/// Bound gamepad buttons, four per line
+pub(crate) fn write_gamepad_controls(gamepad_mapping: &GamepadMapping) -> String {
+    let lines: Vec<String> = gamepad_mapping
+        .labels()
+        .chunks(4)
+        .map(|labels| labels.join(" "))
+        .collect();
+    format!("   GAMEPAD\n   {}", lines.join("\n   "))
 }
 
 pub fn game_pad() -> [[u8; 4]; 4] {
-    LAYOUT
+    KEYPAD_LAYOUT
 }
 
 pub(crate) fn write_hyphen_line<'h>() -> &'h str {
@@ -0,0 +1,143 @@
+use crate::key_mapping::KeyNames;
+use sdl3::gamepad::Button;
+use std::collections::HashMap;
+
+/// Buttons that can be bound, with their name in the configuration and their label
+const BUTTONS: [(Button, &str, &str); 15] = [
+    (Button::South, "a", "A"),
+    (Button::East, "b", "B"),
+    (Button::West, "x", "X"),
+    (Button::North, "y", "Y"),
+    (Button::DPadUp, "dpup", "UP"),
+    (Button::DPadDown, "dpdown", "DOWN"),
+    (Button::DPadLeft, "dpleft", "LEFT"),
+    (Button::DPadRight, "dpright", "RIGHT"),
+    (Button::LeftShoulder, "leftshoulder", "LB"),
+    (Button::RightShoulder, "rightshoulder", "RB"),
+    (Button::LeftStick, "leftstick", "LS"),
+    (Button::RightStick, "rightstick", "RS"),
+    (Button::Back, "back", "BACK"),
+    (Button::Start, "start", "START"),
+    (Button::Guide, "guide", "GUIDE"),
+];
+
+/// Default bindings : the d-pad on 2/4/6/8, A on 5 as most games move with these keys, and
+/// the other buttons on the keys that are left
+const DEFAULT_BINDINGS: [(Button, u8); 12] = [
+    (Button::DPadUp, 0x2),
+    (Button::DPadDown, 0x8),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::South, 0x5),
+    (Button::East, 0xA),
+    (Button::West, 0xB),
+    (Button::North, 0xC),
+    (Button::LeftShoulder, 0x1),
+    (Button::RightShoulder, 0x3),
+    (Button::Back, 0xF),
+    (Button::Start, 0x0),
+];
+
+/// Mapping between the gamepad buttons and the CHIP-8 keypad, shared by every gamepad
+#[derive(Debug, Clone)]
+pub struct GamepadMapping {
+    /// Buttons bound to every keypad key, indexed by keypad key
+    bindings: [Vec<Button>; 16],
+}
+
+impl Default for GamepadMapping {
+    fn default() -> Self {
+        let mut bindings: [Vec<Button>; 16] = Default::default();
+        for (button, key) in DEFAULT_BINDINGS {
+            bindings[key as usize].push(button);
+        }
+        Self { bindings }
+    }
+}
+
+impl GamepadMapping {
+    /// Keypad key bound to a button, if any
+    pub fn key(&self, button: Button) -> Option<u8> {
+        self.bindings
+            .iter()
+            .position(|buttons| buttons.contains(&button))
+            .map(|key| key as u8)
+    }
+
+    /// Binds a keypad key to buttons, replacing its previous bindings and unbinding these
+    /// buttons from other keypad keys
+    pub fn bind(&mut self, key: u8, buttons: &[Button]) {
+        for bound in self.bindings.iter_mut() {
+            bound.retain(|button| !buttons.contains(button));
+        }
+        self.bindings[key as usize] = buttons.to_vec();
+    }
+
+    /// Applies bindings written as `{"5": "a", "2": ["dpup", "y"], ...}`, keypad keys being
+    /// hexadecimal digits
+    pub fn apply_bindings(&mut self, bindings: &HashMap<String, KeyNames>) -> Result<(), String> {
+        for (key, names) in bindings {
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or_else(|| format!("Invalid keypad key {}", key))?;
+            let buttons = names
+                .names()
+                .iter()
+                .map(|name| {
+                    BUTTONS
+                        .iter()
+                        .find(|(_, button_name, _)| button_name.eq_ignore_ascii_case(name))
+                        .map(|(button, _, _)| *button)
+                        .ok_or_else(|| format!("Unknown gamepad button {}", name))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            self.bind(key, &buttons);
+        }
+        Ok(())
+    }
+
+    /// Bound buttons with their keypad key, like `UP:2`, in the order of `BUTTONS`
+    pub fn labels(&self) -> Vec<String> {
+        BUTTONS
+            .iter()
+            .filter_map(|(button, _, label)| {
+                self.key(*button).map(|key| format!("{}:{:X}", label, key))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_mapping() {
+        let mapping = GamepadMapping::default();
+        assert_eq!(mapping.key(Button::DPadUp), Some(0x2));
+        assert_eq!(mapping.key(Button::DPadRight), Some(0x6));
+        assert_eq!(mapping.key(Button::South), Some(0x5));
+        assert_eq!(mapping.key(Button::Guide), None);
+    }
+
+    #[test]
+    fn test_apply_bindings() {
+        let mut mapping = GamepadMapping::default();
+        let bindings = HashMap::from([
+            (
+                "4".to_string(),
+                KeyNames::Many(vec!["dpleft".to_string(), "X".to_string()]),
+            ),
+            ("5".to_string(), KeyNames::One("dpup".to_string())),
+        ]);
+        mapping.apply_bindings(&bindings).unwrap();
+        assert_eq!(mapping.key(Button::West), Some(0x4));
+        assert_eq!(mapping.key(Button::DPadUp), Some(0x5));
+        assert_eq!(mapping.key(Button::South), None);
+        assert!(mapping.labels().contains(&"X:4".to_string()));
+
+        let unknown = HashMap::from([("5".to_string(), KeyNames::One("z".to_string()))]);
+        assert!(mapping.apply_bindings(&unknown).is_err());
+    }
+}
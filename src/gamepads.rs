@@ -0,0 +1,45 @@
+use sdl3::GamepadSubsystem;
+use sdl3::Sdl;
+use sdl3::gamepad::Gamepad;
+
+/// Gamepads currently plugged in, opened and closed as SDL reports them
+pub struct Gamepads {
+    subsystem: Option<GamepadSubsystem>,
+    connected: Vec<Gamepad>,
+}
+
+impl Gamepads {
+    /// Without a gamepad subsystem the emulator still runs, with the keyboard only
+    pub fn new(sdl: &Sdl) -> Self {
+        let subsystem = sdl
+            .gamepad()
+            .inspect_err(|e| eprintln!("Gamepads are not available : {}", e))
+            .ok();
+        Self {
+            subsystem,
+            connected: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, id: u32) {
+        let Some(subsystem) = &self.subsystem else {
+            return;
+        };
+        match subsystem.open(id) {
+            Ok(gamepad) => {
+                println!("Gamepad connected : {}", gamepad.name().unwrap_or_default());
+                self.connected.push(gamepad);
+            }
+            Err(e) => eprintln!("Could not open gamepad {} : {}", id, e),
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.connected
+            .retain(|gamepad| gamepad.id().is_ok_and(|connected| connected != id));
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !self.connected.is_empty()
+    }
+}
@@ -0,0 +1,283 @@
+use crate::cli::{ColorValue, TestArgs};
+use crate::clock::{Clock, TimerModel};
+use crate::interpreter::{Interpreter, InterpreterVariant};
+use crate::random::Random;
+use crate::runner::{FONT, FONT_ADDRESS, load_font, load_rom};
+use crate::screen::Screen;
+use crate::screen_config::{Colors, Dimensions, ScreenConfig};
+use crate::state::State;
+use crate::vip_timing::TimingMode;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+const ON_PIXELS: [char; 5] = ['#', 'X', '1', '*', '█'];
+
+/// Runs a ROM without any window and checks the final screen against a reference
+pub fn run_test(args: &TestArgs) -> Result<(), String> {
+    let key_events = match &args.keys {
+        Some(path) => read_key_events(path)?,
+        None => Vec::new(),
+    };
+    let random = Random::new(args.random, args.seed);
+    let speed = args
+        .speed
+        .unwrap_or(args.instructions_per_frame as u32 * 60);
+    let mut runner = HeadlessRunner::new(&args.rom_path, args.interpreter_variant, random, speed)?;
+    for frame in 0..args.frames {
+        key_events
+            .iter()
+            .filter(|event| event.frame == frame)
+            .for_each(|event| {
+                if event.pressed {
+                    runner.state.keypad.press_key(event.key);
+                } else {
+                    runner.state.keypad.release_key(event.key);
+                }
+            });
+        runner.run_frame();
+    }
+
+    let actual = screen_rows(&runner.state.screen);
+    if let Some(path) = &args.dump {
+        write_screen(path, &actual)?;
+    }
+    let Some(path) = &args.expect else {
+        println!("{}", actual.join("\n"));
+        return Ok(());
+    };
+    let expected = read_screen(path, runner.state.screen.dimensions)?;
+    let mismatches = count_mismatches(&expected, &actual);
+    if mismatches > 0 {
+        eprintln!("{}", actual.join("\n"));
+        return Err(format!(
+            "Screen differs from {} on {} pixels",
+            path.display(),
+            mismatches
+        ));
+    }
+    println!("Screen matches {}", path.display());
+    Ok(())
+}
+
+/// Interpreter and state driven frame by frame, without SDL events, audio or window
+pub struct HeadlessRunner {
+    clock: Clock,
+    interpreter: Interpreter,
+    pub state: State,
+}
+
+impl HeadlessRunner {
+    pub fn new(
+        rom_path: &Path,
+        variant: InterpreterVariant,
+        random: Random,
+        speed: u32,
+    ) -> Result<Self, String> {
+        let colors = Colors::new(ColorValue::White, ColorValue::Black, ColorValue::Red);
+        // The window is never opened, its dimensions do not matter
+        let screen_config = ScreenConfig::new(Dimensions::new(0, 0), colors);
+        let mut state = State::new(&screen_config, random);
+        load_rom(&mut state, rom_path.to_path_buf())
+            .map_err(|e| format!("Could not load ROM {} : {}", rom_path.display(), e))?;
+        load_font(&mut state, FONT, FONT_ADDRESS);
+        Ok(Self {
+            clock: Clock::new(speed, TimerModel::Instructions),
+            interpreter: Interpreter::new(variant, FONT_ADDRESS),
+            state,
+        })
+    }
+
+    pub fn run_frame(&mut self) {
+        for _ in 0..self.clock.advance_frame() {
+            self.interpreter
+                .game_step(&mut self.state, TimingMode::Instructions, 0);
+            for _ in 0..self.clock.cycle(1) {
+                self.state.decrease_timers();
+            }
+        }
+        self.state.keypad.start_frame();
+    }
+}
+
+struct KeyEvent {
+    frame: u32,
+    key: u8,
+    pressed: bool,
+}
+
+/// Reads key events written as one `FRAME KEY down|up` line each, `KEY` being hexadecimal
+fn read_key_events(path: &Path) -> Result<Vec<KeyEvent>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read key events {} : {}", path.display(), e))?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let invalid = || format!("Invalid key event '{}' in {}", line, path.display());
+            let mut parts = line.split_whitespace();
+            let frame = parts.next().and_then(|frame| frame.parse().ok());
+            let key = parts
+                .next()
+                .and_then(|key| u8::from_str_radix(key, 16).ok())
+                .filter(|key| *key < 16);
+            let pressed = match parts.next() {
+                Some("down") => Some(true),
+                Some("up") => Some(false),
+                _ => None,
+            };
+            match (frame, key, pressed, parts.next()) {
+                (Some(frame), Some(key), Some(pressed), None) => Ok(KeyEvent {
+                    frame,
+                    key,
+                    pressed,
+                }),
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+/// Screen as rows of `#` for lit pixels and `.` for the others
+pub fn screen_rows(screen: &Screen) -> Vec<String> {
+    (0..screen.dimensions.height)
+        .map(|y| {
+            (0..screen.dimensions.width)
+                .map(|x| if screen.is_pixel_on(x, y) { '#' } else { '.' })
+                .collect()
+        })
+        .collect()
+}
+
+fn count_mismatches(expected: &[String], actual: &[String]) -> usize {
+    expected
+        .iter()
+        .zip(actual)
+        .map(|(expected, actual)| {
+            expected
+                .chars()
+                .zip(actual.chars())
+                .filter(|(e, a)| e != a)
+                .count()
+        })
+        .sum()
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+/// Reads a reference screen, either an ASCII-art text file or a PNG image
+///
+/// PNG images may be any integer upscale of the screen, a pixel is lit when it is brighter
+/// than mid-grey.
+fn read_screen(path: &Path, dimensions: Dimensions) -> Result<Vec<String>, String> {
+    let rows = if is_png(path) {
+        read_png_screen(path, dimensions)?
+    } else {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {} : {}", path.display(), e))?;
+        content
+            .lines()
+            .map(|line| {
+                line.chars()
+                    .map(|c| if ON_PIXELS.contains(&c) { '#' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect()
+    };
+    // Missing trailing pixels or lines are considered off
+    Ok((0..dimensions.height)
+        .map(|y| {
+            let row = rows.get(y).map(String::as_str).unwrap_or("");
+            format!("{:.<width$}", row, width = dimensions.width)
+        })
+        .collect())
+}
+
+fn read_png_screen(path: &Path, dimensions: Dimensions) -> Result<Vec<String>, String> {
+    let error = |e: &dyn std::fmt::Display| format!("Could not read {} : {}", path.display(), e);
+    let file = File::open(path).map_err(|e| error(&e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| error(&e))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| error(&e))?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    if width == 0 || width % dimensions.width != 0 || height % dimensions.height != 0 {
+        return Err(error(&format!(
+            "{}x{} is not a multiple of {}x{}",
+            width, height, dimensions.width, dimensions.height
+        )));
+    }
+    let channels = info.color_type.samples();
+    let (scale_x, scale_y) = (width / dimensions.width, height / dimensions.height);
+    Ok((0..dimensions.height)
+        .map(|y| {
+            (0..dimensions.width)
+                .map(|x| {
+                    let offset = (y * scale_y + scale_y / 2) * info.line_size
+                        + (x * scale_x + scale_x / 2) * channels;
+                    let pixel = &buffer[offset..offset + channels.min(3)];
+                    let brightness = pixel.iter().map(|c| *c as usize).sum::<usize>() / pixel.len();
+                    if brightness > 127 { '#' } else { '.' }
+                })
+                .collect()
+        })
+        .collect())
+}
+
+fn write_screen(path: &Path, rows: &[String]) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("Could not write {} : {}", path.display(), e);
+    if !is_png(path) {
+        return fs::write(path, rows.join("\n") + "\n").map_err(|e| error(&e));
+    }
+    let width = rows.first().map_or(0, String::len);
+    let file = File::create(path).map_err(|e| error(&e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, rows.len() as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = rows
+        .iter()
+        .flat_map(|row| row.chars().map(|c| if c == '#' { 0xFF } else { 0x00 }))
+        .collect();
+    let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+    writer.write_image_data(&data).map_err(|e| error(&e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::random::RandomMode;
+
+    fn run_rom(rom: &str, variant: InterpreterVariant, frames: u32) -> Vec<String> {
+        let random = Random::new(RandomMode::Seeded, 0);
+        let mut runner = HeadlessRunner::new(Path::new(rom), variant, random, 660).unwrap();
+        for _ in 0..frames {
+            runner.run_frame();
+        }
+        screen_rows(&runner.state.screen)
+    }
+
+    fn expected(path: &str) -> Vec<String> {
+        read_screen(Path::new(path), Dimensions::new(64, 32)).unwrap()
+    }
+
+    #[test]
+    fn test_opcode_rom() {
+        for variant in [InterpreterVariant::CosmacVip, InterpreterVariant::Chip48] {
+            let actual = run_rom("roms/test_opcode.ch8", variant, 120);
+            assert_eq!(actual, expected("roms/expected/test_opcode.txt"));
+        }
+    }
+
+    #[test]
+    fn test_bc_rom() {
+        // BC_Test expects the CHIP-48 shifts, the COSMAC VIP ones make it fail with error 12
+        let actual = run_rom("roms/bc_test.ch8", InterpreterVariant::Chip48, 600);
+        assert_eq!(actual, expected("roms/expected/bc_test.txt"));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::instruction::{Instruction, Operator};
 use crate::state::State;
+use crate::vip_timing::{TimingMode, machine_cycles};
 use clap::ValueEnum;
-use rand::Rng;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -30,10 +30,23 @@ impl Interpreter {
             font_address,
         }
     }
-    pub fn game_step(&self, state: &mut State) {
+    /// Runs one instruction, returns its cost: 1, or the machine cycles it takes on the
+    /// COSMAC VIP with the VIP timing, `cycles_to_interrupt` being then the cycles left before
+    /// the next display interrupt
+    pub fn game_step(
+        &self,
+        state: &mut State,
+        timing: TimingMode,
+        cycles_to_interrupt: u32,
+    ) -> u32 {
         let (byte1, byte2) = fetch(state);
         let instruction = decode(byte1, byte2);
+        let cost = match timing {
+            TimingMode::Instructions => 1,
+            TimingMode::VipCycles => machine_cycles(&instruction, state, cycles_to_interrupt),
+        };
         self.execute(instruction, state);
+        cost
     }
 
     fn execute(&self, instruction: Instruction, state: &mut State) {
@@ -65,10 +78,10 @@ impl Interpreter {
             Instruction::JumpOffset(register_x, offset) => {
                 self.jump_offset(state, register_x, offset)
             }
-            Instruction::Random(rx, value) => state.set_register(rx, {
-                let random: u8 = rand::rng().random();
-                random & value
-            }),
+            Instruction::Random(rx, value) => {
+                let random = state.random.next_byte();
+                state.set_register(rx, random & value)
+            }
             Instruction::Draw(rx, ry, sprite_height) => draw(state, rx, ry, sprite_height),
             Instruction::SkipIfKey(rx, if_pressed) => skip_key_press(state, rx, if_pressed),
             Instruction::ReadDelayTimer(rx) => state.set_register(rx, state.delay_timer),
@@ -92,18 +105,17 @@ impl Interpreter {
         }
     }
     fn memory_copy(&self, state: &mut State, rx: usize, store: bool) {
-        let mut working_index = state.index;
+        let base_address = state.index as usize;
         for i in 0..rx + 1 {
             if store {
-                state.ram[working_index as usize] = state.register(i);
+                state.ram[base_address + i] = state.register(i);
             } else {
-                state.set_register(i, state.ram[working_index as usize]);
+                state.set_register(i, state.ram[base_address + i]);
             }
-            state.index = match self.variant {
-                InterpreterVariant::CosmacVip => working_index,
-                InterpreterVariant::Chip48 => state.index,
-            };
-            working_index += 1;
+        }
+        // The COSMAC VIP leaves the index right after the last register copied
+        if let InterpreterVariant::CosmacVip = self.variant {
+            state.index += rx as u16 + 1;
         }
     }
 
@@ -142,11 +154,13 @@ impl Interpreter {
     }
 }
 
+/// Blocks until a key is pressed and released, then puts it in vx
+///
+/// The instruction is executed again until then, the timers keep running meanwhile.
 fn await_key_press(state: &mut State, rx: usize) {
-    // TODO : Wait for a key to be pressed and released and then put value in vx
-    let found = find_key(state, rx);
-    if !found {
-        state.program_counter -= 2;
+    match state.keypad.await_key_release() {
+        Some(key) => state.set_register(rx, key),
+        None => state.program_counter -= 2,
     }
 }
 
@@ -214,3 +228,522 @@ fn skip_compare(state: &mut State, value1: u8, value2: u8, skip_condition: bool)
         state.program_counter += 2;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::runner::FONT_ADDRESS;
+    use crate::test_fixtures::new_state;
+
+    const VARIANTS: [InterpreterVariant; 2] =
+        [InterpreterVariant::CosmacVip, InterpreterVariant::Chip48];
+
+    fn execute(variant: InterpreterVariant, instruction: Instruction, state: &mut State) {
+        Interpreter::new(variant, FONT_ADDRESS).execute(instruction, state);
+    }
+
+    /// Executes `Arithmetic(rx, ry, operator)` and returns (VX, VF)
+    fn arithmetic(variant: InterpreterVariant, x: u8, y: u8, operator: Operator) -> (u8, u8) {
+        let mut state = new_state();
+        state.set_register(1, x);
+        state.set_register(2, y);
+        execute(variant, Instruction::Arithmetic(1, 2, operator), &mut state);
+        (state.register(1), state.vf())
+    }
+
+    #[test]
+    fn test_game_step_fetches_and_executes() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.ram[0x200] = 0x6A;
+            state.ram[0x201] = 0x42;
+            Interpreter::new(variant, FONT_ADDRESS).game_step(
+                &mut state,
+                TimingMode::Instructions,
+                0,
+            );
+            assert_eq!(state.register(0xA), 0x42);
+            assert_eq!(state.program_counter, 0x202);
+        }
+    }
+
+    #[test]
+    fn test_game_step_cost() {
+        let interpreter = Interpreter::new(InterpreterVariant::CosmacVip, FONT_ADDRESS);
+        for (timing, cost) in [
+            (TimingMode::Instructions, 1),
+            (TimingMode::VipCycles, 40 + 14 + 4),
+        ] {
+            let mut state = new_state();
+            // EXA1 with VX = 0x20, which is not a key
+            state.ram[0x200] = 0xE4;
+            state.ram[0x201] = 0xA1;
+            state.set_register(4, 0x20);
+            assert_eq!(interpreter.game_step(&mut state, timing, 0), cost);
+            assert_eq!(state.program_counter, 0x204);
+        }
+    }
+
+    #[test]
+    fn test_clear_screen() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.screen.draw_pixel(3, 4);
+            execute(variant, Instruction::ClearScreen, &mut state);
+            assert!(!state.screen.is_pixel_on(3, 4));
+        }
+    }
+
+    #[test]
+    fn test_system_does_nothing() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            execute(variant, Instruction::System, &mut state);
+            assert_eq!(state.program_counter, 0x200);
+        }
+    }
+
+    #[test]
+    fn test_jump() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            execute(variant, Instruction::Jump(0x345), &mut state);
+            assert_eq!(state.program_counter, 0x345);
+        }
+    }
+
+    #[test]
+    fn test_subroutine_call_and_return() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            execute(variant, Instruction::SubroutineCall(0x400), &mut state);
+            assert_eq!(state.program_counter, 0x400);
+            assert_eq!(state.stack.read_all().copied().collect::<Vec<_>>(), [0x200]);
+            execute(variant, Instruction::SubroutineReturn, &mut state);
+            assert_eq!(state.program_counter, 0x200);
+            assert_eq!(state.stack.read_all().count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_skip_equal_value() {
+        for variant in VARIANTS {
+            for (value, skip_condition, expected_pc) in [
+                (7, true, 0x202),
+                (8, true, 0x200),
+                (7, false, 0x200),
+                (8, false, 0x202),
+            ] {
+                let mut state = new_state();
+                state.set_register(3, 7);
+                let instruction = Instruction::SkipEqualValue(3, value, skip_condition);
+                execute(variant, instruction, &mut state);
+                assert_eq!(state.program_counter, expected_pc);
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip_equal_register() {
+        for variant in VARIANTS {
+            for (y, skip_condition, expected_pc) in [
+                (7, true, 0x202),
+                (8, true, 0x200),
+                (7, false, 0x200),
+                (8, false, 0x202),
+            ] {
+                let mut state = new_state();
+                state.set_register(3, 7);
+                state.set_register(4, y);
+                let instruction = Instruction::SkipEqualRegister(3, 4, skip_condition);
+                execute(variant, instruction, &mut state);
+                assert_eq!(state.program_counter, expected_pc);
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_register() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            execute(variant, Instruction::SetRegister(5, 0xAB), &mut state);
+            assert_eq!(state.register(5), 0xAB);
+        }
+    }
+
+    #[test]
+    fn test_add_value_register_wraps_without_carry() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.set_register(5, 250);
+            execute(variant, Instruction::AddValueRegister(5, 10), &mut state);
+            assert_eq!(state.register(5), 4);
+            assert_eq!(state.vf(), 0);
+        }
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        for variant in VARIANTS {
+            assert_eq!(arithmetic(variant, 0xF0, 0x3C, Operator::Set).0, 0x3C);
+            assert_eq!(arithmetic(variant, 0xF0, 0x3C, Operator::BinaryOr).0, 0xFC);
+            assert_eq!(arithmetic(variant, 0xF0, 0x3C, Operator::BinaryAnd).0, 0x30);
+            assert_eq!(arithmetic(variant, 0xF0, 0x3C, Operator::BinaryXor).0, 0xCC);
+        }
+    }
+
+    #[test]
+    fn test_add_carry() {
+        for variant in VARIANTS {
+            assert_eq!(arithmetic(variant, 1, 2, Operator::Add), (3, 0));
+            assert_eq!(arithmetic(variant, 255, 0, Operator::Add), (255, 0));
+            assert_eq!(arithmetic(variant, 255, 1, Operator::Add), (0, 1));
+            assert_eq!(arithmetic(variant, 200, 100, Operator::Add), (44, 1));
+        }
+    }
+
+    #[test]
+    fn test_subtract_borrow() {
+        for variant in VARIANTS {
+            assert_eq!(arithmetic(variant, 5, 3, Operator::Subtract), (2, 1));
+            assert_eq!(arithmetic(variant, 3, 3, Operator::Subtract), (0, 1));
+            assert_eq!(arithmetic(variant, 3, 5, Operator::Subtract), (254, 0));
+            assert_eq!(arithmetic(variant, 0, 255, Operator::Subtract), (1, 0));
+            assert_eq!(arithmetic(variant, 255, 0, Operator::Subtract), (255, 1));
+        }
+    }
+
+    #[test]
+    fn test_subtract_inverse_borrow() {
+        for variant in VARIANTS {
+            assert_eq!(arithmetic(variant, 3, 5, Operator::SubtractInverse), (2, 1));
+            assert_eq!(arithmetic(variant, 3, 3, Operator::SubtractInverse), (0, 1));
+            assert_eq!(
+                arithmetic(variant, 5, 3, Operator::SubtractInverse),
+                (254, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_shift_source_register() {
+        use InterpreterVariant::*;
+        // The COSMAC VIP shifts VY into VX, the CHIP-48 shifts VX in place
+        assert_eq!(
+            arithmetic(CosmacVip, 0x00, 0x81, Operator::ShiftR),
+            (0x40, 1)
+        );
+        assert_eq!(
+            arithmetic(CosmacVip, 0xFF, 0x40, Operator::ShiftR),
+            (0x20, 0)
+        );
+        assert_eq!(
+            arithmetic(CosmacVip, 0x00, 0x81, Operator::ShiftL),
+            (0x02, 1)
+        );
+        assert_eq!(
+            arithmetic(CosmacVip, 0xFF, 0x40, Operator::ShiftL),
+            (0x80, 0)
+        );
+        assert_eq!(arithmetic(Chip48, 0x81, 0x00, Operator::ShiftR), (0x40, 1));
+        assert_eq!(arithmetic(Chip48, 0x40, 0xFF, Operator::ShiftR), (0x20, 0));
+        assert_eq!(arithmetic(Chip48, 0x81, 0x00, Operator::ShiftL), (0x02, 1));
+        assert_eq!(arithmetic(Chip48, 0x40, 0xFF, Operator::ShiftL), (0x80, 0));
+    }
+
+    #[test]
+    fn test_flag_wins_when_x_is_vf() {
+        for variant in VARIANTS {
+            for (operator, x, y, flag) in [
+                (Operator::Add, 200, 100, 1),
+                (Operator::Add, 1, 2, 0),
+                (Operator::Subtract, 5, 3, 1),
+                (Operator::Subtract, 3, 5, 0),
+                (Operator::SubtractInverse, 3, 5, 1),
+                (Operator::SubtractInverse, 5, 3, 0),
+                (Operator::ShiftR, 0x81, 0x81, 1),
+                (Operator::ShiftL, 0x40, 0x40, 0),
+            ] {
+                let mut state = new_state();
+                state.set_vf(x);
+                state.set_register(2, y);
+                execute(
+                    variant,
+                    Instruction::Arithmetic(0xF, 2, operator),
+                    &mut state,
+                );
+                assert_eq!(state.vf(), flag);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vf_read_before_flag_when_y_is_vf() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.set_register(1, 10);
+            state.set_vf(20);
+            execute(
+                variant,
+                Instruction::Arithmetic(1, 0xF, Operator::Add),
+                &mut state,
+            );
+            assert_eq!((state.register(1), state.vf()), (30, 0));
+
+            let mut state = new_state();
+            state.set_register(1, 10);
+            state.set_vf(3);
+            execute(
+                variant,
+                Instruction::Arithmetic(1, 0xF, Operator::Subtract),
+                &mut state,
+            );
+            assert_eq!((state.register(1), state.vf()), (7, 1));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown operator")]
+    fn test_unknown_operator_panics() {
+        arithmetic(InterpreterVariant::Chip48, 1, 2, Operator::Unknown(0x8));
+    }
+
+    #[test]
+    fn test_set_index() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            execute(variant, Instruction::SetIndex(0x123), &mut state);
+            assert_eq!(state.index, 0x123);
+        }
+    }
+
+    #[test]
+    fn test_jump_offset_register() {
+        // BNNN jumps to NNN + V0 on the COSMAC VIP, BXNN to XNN + VX on the CHIP-48
+        let mut state = new_state();
+        state.set_register(0, 0x10);
+        state.set_register(2, 0x20);
+        execute(
+            InterpreterVariant::CosmacVip,
+            Instruction::JumpOffset(2, 0x250),
+            &mut state,
+        );
+        assert_eq!(state.program_counter, 0x260);
+        execute(
+            InterpreterVariant::Chip48,
+            Instruction::JumpOffset(2, 0x250),
+            &mut state,
+        );
+        assert_eq!(state.program_counter, 0x270);
+    }
+
+    #[test]
+    fn test_random_is_masked_and_seeded() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            let mut other = new_state();
+            for _ in 0..32 {
+                execute(variant, Instruction::Random(1, 0x0F), &mut state);
+                execute(variant, Instruction::Random(1, 0x0F), &mut other);
+                assert_eq!(state.register(1) & 0xF0, 0);
+                assert_eq!(state.register(1), other.register(1));
+                execute(variant, Instruction::Random(2, 0x00), &mut state);
+                execute(variant, Instruction::Random(2, 0x00), &mut other);
+                assert_eq!(state.register(2), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_and_collision() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.index = 0x300;
+            state.ram[0x300] = 0b1000_0001;
+            state.set_register(1, 2);
+            state.set_register(2, 3);
+            execute(variant, Instruction::Draw(1, 2, 1), &mut state);
+            assert!(state.screen.is_pixel_on(2, 3));
+            assert!(!state.screen.is_pixel_on(3, 3));
+            assert!(state.screen.is_pixel_on(9, 3));
+            assert_eq!(state.vf(), 0);
+            execute(variant, Instruction::Draw(1, 2, 1), &mut state);
+            assert!(!state.screen.is_pixel_on(2, 3));
+            assert_eq!(state.vf(), 1);
+        }
+    }
+
+    #[test]
+    fn test_draw_wraps_origin_and_clips_sprite() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.index = 0x300;
+            state.ram[0x300] = 0xFF;
+            state.ram[0x301] = 0xFF;
+            // 64 + 60 wraps to 60, the sprite is then clipped on the right and bottom edges
+            state.set_register(1, 124);
+            state.set_register(2, 31);
+            execute(variant, Instruction::Draw(1, 2, 2), &mut state);
+            assert!((60..64).all(|x| state.screen.is_pixel_on(x, 31)));
+            assert!((0..4).all(|x| !state.screen.is_pixel_on(x, 31)));
+            assert!((0..64).all(|x| !state.screen.is_pixel_on(x, 0)));
+        }
+    }
+
+    #[test]
+    fn test_skip_if_key() {
+        for variant in VARIANTS {
+            for (pressed, if_pressed, expected_pc) in [
+                (true, true, 0x202),
+                (false, true, 0x200),
+                (true, false, 0x200),
+                (false, false, 0x202),
+            ] {
+                let mut state = new_state();
+                state.set_register(4, 0xB);
+                if pressed {
+                    state.keypad.press_key(0xB);
+                }
+                execute(variant, Instruction::SkipIfKey(4, if_pressed), &mut state);
+                assert_eq!(state.program_counter, expected_pc);
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip_if_key_out_of_range() {
+        // There is no key 0x20, it is never pressed
+        for (if_pressed, expected_pc) in [(true, 0x200), (false, 0x202)] {
+            let mut state = new_state();
+            state.set_register(4, 0x20);
+            state.keypad.set_bits(0xFFFF);
+            execute(
+                InterpreterVariant::Chip48,
+                Instruction::SkipIfKey(4, if_pressed),
+                &mut state,
+            );
+            assert_eq!(state.program_counter, expected_pc);
+        }
+    }
+
+    #[test]
+    fn test_timers() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.set_register(1, 30);
+            state.set_register(2, 40);
+            execute(variant, Instruction::SetDelayTimer(1), &mut state);
+            execute(variant, Instruction::SetSoundTimer(2), &mut state);
+            assert_eq!((state.delay_timer, state.sound_timer), (30, 40));
+            state.decrease_timers();
+            execute(variant, Instruction::ReadDelayTimer(3), &mut state);
+            assert_eq!(state.register(3), 29);
+        }
+    }
+
+    #[test]
+    fn test_add_to_index() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.index = 0xFF0;
+            state.set_register(1, 0x20);
+            execute(variant, Instruction::AddToIndex(1), &mut state);
+            assert_eq!(state.index, 0x1010);
+            assert_eq!(state.vf(), 0);
+        }
+    }
+
+    #[test]
+    fn test_get_key_waits_for_key() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.program_counter = 0x202;
+            execute(variant, Instruction::GetKey(1), &mut state);
+            assert_eq!(state.program_counter, 0x200);
+            // Pressing a key is not enough, it must be released
+            state.program_counter = 0x202;
+            state.keypad.press_key(0x7);
+            execute(variant, Instruction::GetKey(1), &mut state);
+            assert_eq!(state.program_counter, 0x200);
+            state.program_counter = 0x202;
+            state.keypad.release_key(0x7);
+            execute(variant, Instruction::GetKey(1), &mut state);
+            assert_eq!(state.program_counter, 0x202);
+            assert_eq!(state.register(1), 0x7);
+        }
+    }
+
+    #[test]
+    fn test_timers_run_while_waiting_for_key() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.delay_timer = 10;
+            for _ in 0..3 {
+                state.program_counter = 0x202;
+                execute(variant, Instruction::GetKey(1), &mut state);
+                state.decrease_timers();
+            }
+            assert_eq!(state.program_counter, 0x200);
+            assert_eq!(state.delay_timer, 7);
+        }
+    }
+
+    #[test]
+    fn test_font_character() {
+        for variant in VARIANTS {
+            let mut state = new_state();
+            state.set_register(1, 0xA);
+            execute(variant, Instruction::FontCharacter(1), &mut state);
+            assert_eq!(state.index, FONT_ADDRESS + 50);
+            // Only the low nibble is used
+            state.set_register(1, 0x3F);
+            execute(variant, Instruction::FontCharacter(1), &mut state);
+            assert_eq!(state.index, FONT_ADDRESS + 75);
+        }
+    }
+
+    #[test]
+    fn test_decimal_conversion() {
+        for variant in VARIANTS {
+            for (value, digits) in [
+                (0, [0, 0, 0]),
+                (9, [0, 0, 9]),
+                (10, [0, 1, 0]),
+                (99, [0, 9, 9]),
+                (100, [1, 0, 0]),
+                (109, [1, 0, 9]),
+                (255, [2, 5, 5]),
+            ] {
+                let mut state = new_state();
+                state.index = 0x300;
+                state.set_register(6, value);
+                execute(variant, Instruction::DecimalConversion(6), &mut state);
+                assert_eq!(state.ram[0x300..0x303], digits);
+                assert_eq!(state.index, 0x300);
+            }
+        }
+    }
+
+    #[test]
+    fn test_store_and_load_registers() {
+        for (variant, final_index) in [
+            (InterpreterVariant::CosmacVip, 0x304),
+            (InterpreterVariant::Chip48, 0x300),
+        ] {
+            let mut state = new_state();
+            (0..16).for_each(|i| state.set_register(i, i as u8 + 1));
+            state.index = 0x300;
+            execute(variant, Instruction::StoreRegisters(3), &mut state);
+            assert_eq!(state.ram[0x300..0x305], [1, 2, 3, 4, 0]);
+            assert_eq!(state.index, final_index);
+
+            let mut state = new_state();
+            state.ram[0x300..0x305].copy_from_slice(&[9, 8, 7, 6, 5]);
+            state.index = 0x300;
+            execute(variant, Instruction::LoadRegisters(3), &mut state);
+            let registers: Vec<u8> = (0..5).map(|i| state.register(i)).collect();
+            assert_eq!(registers, [9, 8, 7, 6, 0]);
+            assert_eq!(state.index, final_index);
+        }
+    }
+}
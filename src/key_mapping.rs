@@ -0,0 +1,307 @@
+use crate::keypad::KEYPAD_LAYOUT;
+use clap::ValueEnum;
+use sdl3::keyboard::Scancode;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Keyboard layouts the mapping can start from
+///
+/// SDL scancodes are physical key positions, so the QWERTY, AZERTY, QWERTZ and Dvorak presets
+/// all bind the same 4x4 block on the left of the keyboard and only differ by the key labels
+/// shown in the controls panel. The numpad preset binds the numeric keypad instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum KeyLayout {
+    #[default]
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+    Numpad,
+}
+
+/// Physical keys of the left block of the keyboard, in the order of the `KEYPAD_LAYOUT`
+const BLOCK_KEYS: [[Scancode; 4]; 4] = [
+    [Scancode::_1, Scancode::_2, Scancode::_3, Scancode::_4],
+    [Scancode::Q, Scancode::W, Scancode::E, Scancode::R],
+    [Scancode::A, Scancode::S, Scancode::D, Scancode::F],
+    [Scancode::Z, Scancode::X, Scancode::C, Scancode::V],
+];
+
+const NUMPAD_KEYS: [[Scancode; 4]; 4] = [
+    [
+        Scancode::Kp7,
+        Scancode::Kp8,
+        Scancode::Kp9,
+        Scancode::KpMultiply,
+    ],
+    [
+        Scancode::Kp4,
+        Scancode::Kp5,
+        Scancode::Kp6,
+        Scancode::KpMinus,
+    ],
+    [
+        Scancode::Kp1,
+        Scancode::Kp2,
+        Scancode::Kp3,
+        Scancode::KpPlus,
+    ],
+    [
+        Scancode::Kp0,
+        Scancode::KpPeriod,
+        Scancode::KpEnter,
+        Scancode::KpDivide,
+    ],
+];
+
+/// Keys handled by the emulator before the game ever sees them: the function keys, F11
+/// switching the display modes, and Tab cycling the palettes
+pub(crate) const RESERVED_KEYS: [Scancode; 13] = [
+    Scancode::F1,
+    Scancode::F2,
+    Scancode::F3,
+    Scancode::F4,
+    Scancode::F5,
+    Scancode::F6,
+    Scancode::F7,
+    Scancode::F8,
+    Scancode::F9,
+    Scancode::F10,
+    Scancode::F11,
+    Scancode::F12,
+    Scancode::Tab,
+];
+
+const QWERTY_LABELS: [[&str; 4]; 4] = [
+    ["1", "2", "3", "4"],
+    ["Q", "W", "E", "R"],
+    ["A", "S", "D", "F"],
+    ["Z", "X", "C", "V"],
+];
+const AZERTY_LABELS: [[&str; 4]; 4] = [
+    ["1", "2", "3", "4"],
+    ["A", "Z", "E", "R"],
+    ["Q", "S", "D", "F"],
+    ["W", "X", "C", "V"],
+];
+const QWERTZ_LABELS: [[&str; 4]; 4] = [
+    ["1", "2", "3", "4"],
+    ["Q", "W", "E", "R"],
+    ["A", "S", "D", "F"],
+    ["Y", "X", "C", "V"],
+];
+const DVORAK_LABELS: [[&str; 4]; 4] = [
+    ["1", "2", "3", "4"],
+    ["'", ",", ".", "P"],
+    ["A", "O", "E", "U"],
+    [";", "Q", "J", "K"],
+];
+const NUMPAD_LABELS: [[&str; 4]; 4] = [
+    ["7", "8", "9", "*"],
+    ["4", "5", "6", "-"],
+    ["1", "2", "3", "+"],
+    ["0", ".", "ENT", "/"],
+];
+
+impl KeyLayout {
+    fn keys(self) -> [[Scancode; 4]; 4] {
+        match self {
+            KeyLayout::Numpad => NUMPAD_KEYS,
+            _ => BLOCK_KEYS,
+        }
+    }
+
+    fn labels(self) -> [[&'static str; 4]; 4] {
+        match self {
+            KeyLayout::Qwerty => QWERTY_LABELS,
+            KeyLayout::Azerty => AZERTY_LABELS,
+            KeyLayout::Qwertz => QWERTZ_LABELS,
+            KeyLayout::Dvorak => DVORAK_LABELS,
+            KeyLayout::Numpad => NUMPAD_LABELS,
+        }
+    }
+
+    /// Label of a physical key of the preset, if it belongs to it
+    fn label(self, scancode: Scancode) -> Option<&'static str> {
+        self.keys()
+            .iter()
+            .flatten()
+            .zip(self.labels().iter().flatten())
+            .find(|(key, _)| **key == scancode)
+            .map(|(_, label)| *label)
+    }
+}
+
+/// Keyboard keys or gamepad buttons bound to a keypad key, a single name or a list of them
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum KeyNames {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyNames {
+    pub(crate) fn names(&self) -> &[String] {
+        match self {
+            KeyNames::One(name) => std::slice::from_ref(name),
+            KeyNames::Many(names) => names,
+        }
+    }
+}
+
+/// Mapping between the keyboard scancodes and the CHIP-8 keypad, several physical keys may
+/// be bound to the same keypad key
+#[derive(Debug, Clone)]
+pub struct KeyMapping {
+    layout: KeyLayout,
+    /// Physical keys bound to every keypad key, indexed by keypad key
+    bindings: [Vec<Scancode>; 16],
+}
+
+impl Default for KeyMapping {
+    fn default() -> Self {
+        Self::new(KeyLayout::default())
+    }
+}
+
+impl KeyMapping {
+    pub fn new(layout: KeyLayout) -> Self {
+        let mut bindings: [Vec<Scancode>; 16] = Default::default();
+        for (scancode, key) in layout
+            .keys()
+            .iter()
+            .flatten()
+            .zip(KEYPAD_LAYOUT.iter().flatten())
+        {
+            bindings[*key as usize].push(*scancode);
+        }
+        Self { layout, bindings }
+    }
+
+    /// Keypad key bound to a scancode, if any
+    pub fn key(&self, scancode: Scancode) -> Option<u8> {
+        self.bindings
+            .iter()
+            .position(|scancodes| scancodes.contains(&scancode))
+            .map(|key| key as u8)
+    }
+
+    /// Binds a keypad key to physical keys, replacing its previous bindings and unbinding
+    /// these physical keys from other keypad keys
+    pub fn bind(&mut self, key: u8, scancodes: &[Scancode]) {
+        for bound in self.bindings.iter_mut() {
+            bound.retain(|scancode| !scancodes.contains(scancode));
+        }
+        self.bindings[key as usize] = scancodes.to_vec();
+    }
+
+    /// Applies bindings written as `{"C": "4", "5": ["W", "Up"], ...}`, keypad keys being
+    /// hexadecimal digits and physical keys SDL scancode names
+    pub fn apply_bindings(&mut self, bindings: &HashMap<String, KeyNames>) -> Result<(), String> {
+        for (key, names) in bindings {
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or_else(|| format!("Invalid keypad key {}", key))?;
+            let scancodes = names
+                .names()
+                .iter()
+                .map(|name| {
+                    Scancode::from_name(name)
+                        .ok_or_else(|| format!("Unknown keyboard key {}", name))
+                        .and_then(bindable)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            self.bind(key, &scancodes);
+        }
+        Ok(())
+    }
+
+    /// Label of the first physical key bound to a keypad key, empty when it is unbound
+    pub fn label(&self, key: u8) -> String {
+        match self.bindings[key as usize].first() {
+            Some(scancode) => match self.layout.label(*scancode) {
+                Some(label) => label.to_string(),
+                None => scancode.name().to_uppercase(),
+            },
+            None => String::new(),
+        }
+    }
+}
+
+/// Refuses the keys the game would never receive
+fn bindable(scancode: Scancode) -> Result<Scancode, String> {
+    match RESERVED_KEYS.contains(&scancode) {
+        true => Err(format!(
+            "{:?} is reserved by the emulator, it cannot be bound",
+            scancode
+        )),
+        false => Ok(scancode),
+    }
+}
+
+/// Parses a `--bind` argument written as `KEY=NAME[,NAME...]`
+pub fn parse_binding(binding: &str) -> Result<(String, KeyNames), String> {
+    let (key, names) = binding
+        .split_once('=')
+        .ok_or_else(|| format!("Expected KEY=NAME[,NAME...], got {}", binding))?;
+    let names = names
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .collect();
+    Ok((key.trim().to_string(), KeyNames::Many(names)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        let qwerty = KeyMapping::new(KeyLayout::Qwerty);
+        assert_eq!(qwerty.key(Scancode::Q), Some(0x4));
+        assert_eq!(qwerty.key(Scancode::V), Some(0xF));
+        assert_eq!(qwerty.label(0x4), "Q");
+
+        let azerty = KeyMapping::new(KeyLayout::Azerty);
+        assert_eq!(azerty.key(Scancode::Q), Some(0x4));
+        assert_eq!(azerty.label(0x4), "A");
+
+        let numpad = KeyMapping::new(KeyLayout::Numpad);
+        assert_eq!(numpad.key(Scancode::Kp5), Some(0x5));
+        assert_eq!(numpad.key(Scancode::KpPeriod), Some(0x0));
+        assert_eq!(numpad.key(Scancode::Q), None);
+    }
+
+    #[test]
+    fn test_multiple_keys_per_keypad_key() {
+        let mut mapping = KeyMapping::default();
+        mapping.bind(0x5, &[Scancode::W, Scancode::Up]);
+        assert_eq!(mapping.key(Scancode::W), Some(0x5));
+        assert_eq!(mapping.key(Scancode::Up), Some(0x5));
+        // Binding W to another keypad key leaves the other bindings of 5 alone
+        mapping.bind(0x8, &[Scancode::W]);
+        assert_eq!(mapping.key(Scancode::W), Some(0x8));
+        assert_eq!(mapping.key(Scancode::Up), Some(0x5));
+    }
+
+    #[test]
+    fn test_reserved_keys() {
+        assert!(bindable(Scancode::Tab).is_err());
+        assert!(bindable(Scancode::F11).is_err());
+        assert!(bindable(Scancode::F1).is_err());
+        assert_eq!(bindable(Scancode::W), Ok(Scancode::W));
+    }
+
+    #[test]
+    fn test_parse_binding() {
+        assert_eq!(
+            parse_binding("5=W, Up").unwrap(),
+            (
+                "5".to_string(),
+                KeyNames::Many(vec!["W".to_string(), "Up".to_string()])
+            )
+        );
+        assert!(parse_binding("5").is_err());
+    }
+}
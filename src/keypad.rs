@@ -1,27 +1,252 @@
-use std::collections::HashMap;
+use std::time::Instant;
 
+/// Physical layout of the 16 keys of the keypad
+pub const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// A key press or release, with the time it was received
+#[derive(Debug, Clone, Copy)]
+pub struct KeyEvent {
+    pub key: u8,
+    pub pressed: bool,
+    pub time: Instant,
+}
+
+/// State of the 16 keys, along with the presses and releases of the current frame
+#[derive(Clone)]
 pub struct Keypad {
-    keys: HashMap<u8, bool>,
+    /// Bit N is set when key N is held
+    keys: u16,
+    /// Keys pressed since the start of the frame
+    pressed: u16,
+    /// Keys released since the start of the frame
+    released: u16,
+    /// Presses and releases since the start of the frame, oldest first
+    events: Vec<KeyEvent>,
+    /// Whether an FX0A instruction is waiting for a key
+    waiting: bool,
+    /// Keys pressed since the wait started
+    pressed_while_waiting: u16,
+    /// First of these keys to be released, which ends the wait
+    released_while_waiting: Option<u8>,
 }
 
 impl Keypad {
     pub fn new() -> Self {
-        let mut keys = HashMap::new();
-        (0u8..16u8).for_each(|i| {
-            keys.insert(i, false);
-        });
-        Self { keys }
+        Self {
+            keys: 0,
+            pressed: 0,
+            released: 0,
+            events: Vec::new(),
+            waiting: false,
+            pressed_while_waiting: 0,
+            released_while_waiting: None,
+        }
     }
 
+    /// Whether a key is held, keys above 0xF never being
     pub fn is_pressed(&self, key: u8) -> bool {
-        self.keys.get(&key).is_some_and(|pressed| *pressed)
+        key < 16 && self.keys & 1 << key != 0
     }
 
     pub fn press_key(&mut self, key: u8) {
-        self.keys.insert(key, true);
+        if key >= 16 || self.is_pressed(key) {
+            return;
+        }
+        self.keys |= 1 << key;
+        self.pressed |= 1 << key;
+        self.push_event(key, true);
+        if self.waiting {
+            self.pressed_while_waiting |= 1 << key;
+        }
     }
 
     pub fn release_key(&mut self, key: u8) {
-        self.keys.insert(key, false);
+        if key >= 16 || !self.is_pressed(key) {
+            return;
+        }
+        self.keys &= !(1 << key);
+        self.released |= 1 << key;
+        self.push_event(key, false);
+        if self.waiting
+            && self.pressed_while_waiting & 1 << key != 0
+            && self.released_while_waiting.is_none()
+        {
+            self.released_while_waiting = Some(key);
+        }
+    }
+
+    fn push_event(&mut self, key: u8, pressed: bool) {
+        self.events.push(KeyEvent {
+            key,
+            pressed,
+            time: Instant::now(),
+        });
+    }
+
+    /// Clears the presses and releases of the previous frame
+    pub fn start_frame(&mut self) {
+        self.pressed = 0;
+        self.released = 0;
+        self.events.clear();
+    }
+
+    /// Keys pressed since the start of the frame, as a bitmask
+    pub fn pressed_this_frame(&self) -> u16 {
+        self.pressed
+    }
+
+    /// Keys released since the start of the frame, as a bitmask
+    pub fn released_this_frame(&self) -> u16 {
+        self.released
+    }
+
+    /// Presses and releases since the start of the frame, to measure the input lag
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// Keys both pressed and released since the start of the frame, which the state at the
+    /// end of the frame alone does not show
+    pub fn tapped_this_frame(&self) -> u16 {
+        self.pressed_this_frame() & self.released_this_frame() & !self.keys
+    }
+
+    /// Waits for a key to be pressed then released, as FX0A does
+    ///
+    /// The first call starts the wait and the following ones return the key once it is
+    /// over. Keys already held when the wait starts must be released and pressed again.
+    pub fn await_key_release(&mut self) -> Option<u8> {
+        if !self.waiting {
+            self.waiting = true;
+            self.pressed_while_waiting = 0;
+            self.released_while_waiting = None;
+            return None;
+        }
+        let key = self.released_while_waiting.take()?;
+        self.cancel_wait();
+        Some(key)
+    }
+
+    /// Forgets the wait in progress, if any
+    pub fn cancel_wait(&mut self) {
+        self.waiting = false;
+        self.pressed_while_waiting = 0;
+        self.released_while_waiting = None;
+    }
+
+    /// State of the 16 keys as a bitmask, bit N being set when key N is pressed
+    pub fn bits(&self) -> u16 {
+        self.keys
+    }
+
+    /// Sets the state of the 16 keys, the changes being seen as key presses and releases
+    pub fn set_bits(&mut self, bits: u16) {
+        (0u8..16u8).for_each(|key| {
+            if bits & 1 << key != 0 {
+                self.press_key(key);
+            } else {
+                self.release_key(key);
+            }
+        });
+    }
+
+    /// Presses and releases the tapped keys, the way they were during the recorded frame
+    pub fn tap_keys(&mut self, taps: u16) {
+        (0u8..16u8)
+            .filter(|key| taps & 1 << key != 0)
+            .for_each(|key| {
+                self.press_key(key);
+                self.release_key(key);
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_await_key_release() {
+        let mut keypad = Keypad::new();
+        // A key held before the wait starts does not count
+        keypad.press_key(0x3);
+        assert_eq!(keypad.await_key_release(), None);
+        keypad.release_key(0x3);
+        assert_eq!(keypad.await_key_release(), None);
+
+        keypad.press_key(0x5);
+        assert_eq!(keypad.await_key_release(), None);
+        keypad.set_bits(0);
+        assert_eq!(keypad.await_key_release(), Some(0x5));
+        // The next wait starts over
+        keypad.press_key(0x6);
+        keypad.release_key(0x6);
+        assert_eq!(keypad.await_key_release(), None);
+    }
+
+    #[test]
+    fn test_frame_edges() {
+        let mut keypad = Keypad::new();
+        keypad.press_key(0x1);
+        keypad.press_key(0x2);
+        keypad.release_key(0x2);
+        // Pressing a held key again is not a new press
+        keypad.press_key(0x1);
+        assert_eq!(keypad.bits(), 0b10);
+        assert_eq!(keypad.pressed_this_frame(), 0b110);
+        assert_eq!(keypad.released_this_frame(), 0b100);
+        assert_eq!(keypad.tapped_this_frame(), 0b100);
+
+        keypad.start_frame();
+        assert_eq!(keypad.bits(), 0b10);
+        assert_eq!(keypad.pressed_this_frame(), 0);
+        assert_eq!(keypad.released_this_frame(), 0);
+    }
+
+    #[test]
+    fn test_timestamped_events() {
+        let mut keypad = Keypad::new();
+        let before = Instant::now();
+        keypad.press_key(0x4);
+        keypad.press_key(0x4);
+        keypad.release_key(0x4);
+        keypad.release_key(0x9);
+        let after = Instant::now();
+        let events: Vec<(u8, bool)> = keypad
+            .events()
+            .iter()
+            .map(|event| (event.key, event.pressed))
+            .collect();
+        assert_eq!(events, [(0x4, true), (0x4, false)]);
+        assert!(
+            keypad
+                .events()
+                .windows(2)
+                .all(|pair| pair[0].time <= pair[1].time)
+        );
+        assert!(
+            keypad
+                .events()
+                .iter()
+                .all(|event| before <= event.time && event.time <= after)
+        );
+
+        keypad.start_frame();
+        assert!(keypad.events().is_empty());
+    }
+
+    #[test]
+    fn test_keys_out_of_range_are_ignored() {
+        let mut keypad = Keypad::new();
+        keypad.press_key(0x10);
+        keypad.press_key(0xFF);
+        assert_eq!(keypad.bits(), 0);
+        keypad.release_key(0x10);
+        assert_eq!(keypad.bits(), 0);
     }
 }
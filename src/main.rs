@@ -1,25 +1,48 @@
 extern crate sdl3;
 
-use crate::cli::Cli;
+use crate::cli::{Cli, Command};
+use crate::config_file::ConfigFile;
 use crate::runner::Runner;
 use clap::Parser;
 
 mod audio_player;
 mod cli;
+mod clock;
 mod config;
+mod config_file;
+mod control_server;
 mod game_window;
+mod gamepad_mapping;
+mod gamepads;
+mod headless;
 mod instruction;
 mod interpreter;
+mod key_mapping;
 mod keypad;
+mod movie;
+mod panel_layout;
+mod phosphor_filter;
+mod random;
+mod recent_roms;
+mod rom_browser;
+mod rom_profile;
 mod runner;
 mod screen;
 mod screen_config;
+mod scripting;
 mod stack;
 mod state;
+#[cfg(test)]
+mod test_fixtures;
+mod vip_timing;
 
 fn main() -> Result<(), String> {
     let cli = Cli::parse();
-    let mut runner = Runner::init(cli.to_config());
+    if let Some(Command::Test(args)) = &cli.command {
+        return headless::run_test(args);
+    }
+    let config_file = ConfigFile::load(cli.config.as_deref())?;
+    let mut runner = Runner::init(cli.to_config(&config_file)?);
     runner.run();
     Ok(())
 }
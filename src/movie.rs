@@ -0,0 +1,383 @@
+use crate::interpreter::InterpreterVariant;
+use crate::random::RandomMode;
+use crate::vip_timing::TimingMode;
+use clap::ValueEnum;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const MOVIE_MAGIC: &str = "CHIP8MOVIE 2";
+const RESET_LINE: &str = "reset";
+const SPEED_PREFIX: &str = "speed ";
+
+/// Header of a movie, everything needed to start the replay in the recorded conditions
+#[derive(Debug, Clone)]
+pub struct MovieHeader {
+    pub seed: u64,
+    pub random_mode: RandomMode,
+    pub rom_hash: String,
+    pub variant: InterpreterVariant,
+    /// Instructions per second at the start, which sets when the timers tick
+    pub speed: u32,
+    /// Whether the speed counts instructions or COSMAC VIP machine cycles
+    pub timing: TimingMode,
+}
+
+/// One line of a movie
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieEntry {
+    /// A frame that executed `instructions` instructions with the keypad in state `keys`,
+    /// the `taps` keys having been pressed and released in between
+    Frame {
+        keys: u16,
+        taps: u16,
+        instructions: u32,
+    },
+    /// The emulation was reset
+    Reset,
+    /// The instructions are executed at `speed` Hz from now on, which sets when the timers tick
+    Speed(u32),
+}
+
+/// Writes the movie line by line as frames are emulated
+///
+/// The file is a plain text file : a header with the seed, random mode, ROM hash, interpreter
+/// variant, speed and timing, followed by one `KKKK N` line per emulated frame, `KKKK` being
+/// the keypad bitmask in hexadecimal and `N` the number of instructions executed. Keys pressed
+/// and released within a single frame are added as a third `TTTT` bitmask. Frames executing
+/// no instruction are only written when the keys change. `speed N` lines give the
+/// instructions per second when the speed is changed during the recording.
+pub struct MovieRecorder {
+    writer: BufWriter<File>,
+    /// Keypad state of the last frame written
+    keys: u16,
+}
+
+impl MovieRecorder {
+    pub fn create(path: &Path, header: &MovieHeader) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", MOVIE_MAGIC)?;
+        writeln!(writer, "seed {}", header.seed)?;
+        let random_mode = header
+            .random_mode
+            .to_possible_value()
+            .expect("Modes are never skipped");
+        writeln!(writer, "random {}", random_mode.get_name())?;
+        writeln!(writer, "rom {}", header.rom_hash)?;
+        let variant = header
+            .variant
+            .to_possible_value()
+            .expect("Variants are never skipped");
+        writeln!(writer, "variant {}", variant.get_name())?;
+        writeln!(writer, "speed {}", header.speed)?;
+        let timing = header
+            .timing
+            .to_possible_value()
+            .expect("Modes are never skipped");
+        writeln!(writer, "timing {}", timing.get_name())?;
+        Ok(Self { writer, keys: 0 })
+    }
+
+    pub fn record(&mut self, entry: MovieEntry) -> std::io::Result<()> {
+        if let MovieEntry::Frame {
+            keys,
+            taps,
+            instructions,
+        } = entry
+        {
+            // Frames where nothing happens are left out, the others keep the key edges even
+            // when they execute no instruction, as below 60 Hz
+            if instructions == 0 && taps == 0 && keys == self.keys {
+                return Ok(());
+            }
+            self.keys = keys;
+        }
+        match entry {
+            MovieEntry::Frame {
+                keys,
+                taps: 0,
+                instructions,
+            } => writeln!(self.writer, "{:04X} {}", keys, instructions),
+            MovieEntry::Frame {
+                keys,
+                taps,
+                instructions,
+            } => writeln!(self.writer, "{:04X} {} {:04X}", keys, instructions, taps),
+            MovieEntry::Reset => writeln!(self.writer, "{}", RESET_LINE),
+            MovieEntry::Speed(speed) => writeln!(self.writer, "{}{}", SPEED_PREFIX, speed),
+        }
+    }
+}
+
+impl Drop for MovieRecorder {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Replays the entries of a movie previously written by a `MovieRecorder`
+pub struct MoviePlayer {
+    pub header: MovieHeader,
+    entries: Vec<MovieEntry>,
+    position: usize,
+}
+
+impl MoviePlayer {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read movie {} : {}", path.display(), e))?;
+        Self::parse(&content).map_err(|e| format!("Invalid movie {} : {}", path.display(), e))
+    }
+
+    fn parse(content: &str) -> Result<Self, String> {
+        let mut lines = content.lines();
+        if lines.next() != Some(MOVIE_MAGIC) {
+            return Err("not a movie file".to_string());
+        }
+        let seed = header_value(lines.next(), "seed")?
+            .parse()
+            .map_err(|_| "invalid seed")?;
+        let random_mode = RandomMode::from_str(header_value(lines.next(), "random")?, true)?;
+        let rom_hash = header_value(lines.next(), "rom")?.to_string();
+        let variant = InterpreterVariant::from_str(header_value(lines.next(), "variant")?, true)?;
+        let speed = header_value(lines.next(), "speed")?
+            .parse()
+            .ok()
+            .filter(|speed| *speed > 0)
+            .ok_or("invalid speed")?;
+        let timing = TimingMode::from_str(header_value(lines.next(), "timing")?, false)?;
+        let entries = lines
+            .filter(|line| !line.trim().is_empty())
+            .map(parse_entry)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            header: MovieHeader {
+                seed,
+                random_mode,
+                rom_hash,
+                variant,
+                speed,
+                timing,
+            },
+            entries,
+            position: 0,
+        })
+    }
+
+    /// Next entry of the movie, `None` once the replay is over
+    pub fn next_entry(&mut self) -> Option<MovieEntry> {
+        let entry = self.entries.get(self.position).copied();
+        self.position += 1;
+        entry
+    }
+}
+
+fn header_value<'l>(line: Option<&'l str>, name: &str) -> Result<&'l str, String> {
+    line.and_then(|line| line.strip_prefix(name))
+        .and_then(|value| value.strip_prefix(' '))
+        .ok_or_else(|| format!("missing {} header", name))
+}
+
+fn parse_entry(line: &str) -> Result<MovieEntry, String> {
+    if line == RESET_LINE {
+        return Ok(MovieEntry::Reset);
+    }
+    let invalid = || format!("invalid frame '{}'", line);
+    if let Some(speed) = line.strip_prefix(SPEED_PREFIX) {
+        return match speed.parse() {
+            Ok(speed) if speed > 0 => Ok(MovieEntry::Speed(speed)),
+            _ => Err(invalid()),
+        };
+    }
+    let mut fields = line.split(' ');
+    let (Some(keys), Some(instructions), taps, None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err(invalid());
+    };
+    Ok(MovieEntry::Frame {
+        keys: u16::from_str_radix(keys, 16).map_err(|_| invalid())?,
+        taps: taps
+            .map(|taps| u16::from_str_radix(taps, 16))
+            .transpose()
+            .map_err(|_| invalid())?
+            .unwrap_or(0),
+        instructions: instructions.parse().map_err(|_| invalid())?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keypad::Keypad;
+
+    #[test]
+    fn test_parse_entry() {
+        assert_eq!(
+            parse_entry("0010 11").unwrap(),
+            MovieEntry::Frame {
+                keys: 0x10,
+                taps: 0,
+                instructions: 11
+            }
+        );
+        assert_eq!(
+            parse_entry("0000 11 0020").unwrap(),
+            MovieEntry::Frame {
+                keys: 0,
+                taps: 0x20,
+                instructions: 11
+            }
+        );
+        assert_eq!(parse_entry("reset").unwrap(), MovieEntry::Reset);
+        assert_eq!(parse_entry("speed 700").unwrap(), MovieEntry::Speed(700));
+        assert!(parse_entry("speed 0").is_err());
+        assert!(parse_entry("timing vip-cycles").is_err());
+        assert!(parse_entry("0000").is_err());
+        assert!(parse_entry("0000 11 0020 1").is_err());
+    }
+
+    const HEADER: &str = "CHIP8MOVIE 2\nseed 42\nrandom seeded\nrom abc\nvariant chip48\n";
+
+    #[test]
+    fn test_parse_header() {
+        let movie = format!("{}speed 700\ntiming vip-cycles\n0000 11\n", HEADER);
+        let player = MoviePlayer::parse(&movie).unwrap();
+        assert_eq!(player.header.seed, 42);
+        assert_eq!(player.header.rom_hash, "abc");
+        assert_eq!(player.header.speed, 700);
+        assert_eq!(player.header.timing, TimingMode::VipCycles);
+        assert_eq!(player.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_speed_and_timing_are_required() {
+        assert!(MoviePlayer::parse(&format!("{}timing instructions\n", HEADER)).is_err());
+        assert!(MoviePlayer::parse(&format!("{}speed 700\n0000 11\n", HEADER)).is_err());
+        assert!(MoviePlayer::parse(&format!("{}speed 0\ntiming instructions\n", HEADER)).is_err());
+        // The first version of the format had neither
+        let old = "CHIP8MOVIE 1\nseed 42\nrandom seeded\nrom abc\nvariant chip48\n0000 11\n";
+        assert!(MoviePlayer::parse(old).is_err());
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("chip8-{}.c8m", std::process::id()));
+        let header = MovieHeader {
+            seed: 7,
+            random_mode: RandomMode::CosmacVip,
+            rom_hash: "f1cf".to_string(),
+            variant: InterpreterVariant::CosmacVip,
+            speed: 660,
+            timing: TimingMode::Instructions,
+        };
+        let entries = [
+            MovieEntry::Frame {
+                keys: 0x20,
+                taps: 0,
+                instructions: 11,
+            },
+            MovieEntry::Speed(720),
+            MovieEntry::Reset,
+            MovieEntry::Frame {
+                keys: 0,
+                taps: 0x1,
+                instructions: 12,
+            },
+        ];
+        let mut recorder = MovieRecorder::create(&path, &header).unwrap();
+        for entry in entries {
+            recorder.record(entry).unwrap();
+        }
+        drop(recorder);
+
+        let mut player = MoviePlayer::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(player.header.seed, 7);
+        assert_eq!(player.header.random_mode, RandomMode::CosmacVip);
+        assert_eq!(player.header.rom_hash, "f1cf");
+        assert!(matches!(
+            player.header.variant,
+            InterpreterVariant::CosmacVip
+        ));
+        assert_eq!(player.header.speed, 660);
+        for entry in entries {
+            assert_eq!(player.next_entry(), Some(entry));
+        }
+        assert_eq!(player.next_entry(), None);
+    }
+
+    #[test]
+    fn test_replay_below_60_hz() {
+        let path = std::env::temp_dir().join(format!("chip8-slow-{}.c8m", std::process::id()));
+        let header = MovieHeader {
+            seed: 0,
+            random_mode: RandomMode::Seeded,
+            rom_hash: "f1cf".to_string(),
+            variant: InterpreterVariant::CosmacVip,
+            speed: 20,
+            timing: TimingMode::Instructions,
+        };
+        // An FX0A waiting at 20 Hz, which executes an instruction every third frame, while
+        // the keys change on the frames in between
+        let presses: [&[(u8, bool)]; 12] = [
+            &[],
+            &[],
+            &[],
+            &[(0x4, true)],
+            &[(0x4, false)],
+            &[],
+            &[],
+            &[],
+            &[],
+            &[(0x2, true), (0x2, false)],
+            &[],
+            &[],
+        ];
+        let mut recorder = MovieRecorder::create(&path, &header).unwrap();
+        let mut keypad = Keypad::new();
+        let mut live = Vec::new();
+        for (frame, presses) in presses.iter().enumerate() {
+            keypad.start_frame();
+            for (key, pressed) in presses.iter() {
+                if *pressed {
+                    keypad.press_key(*key);
+                } else {
+                    keypad.release_key(*key);
+                }
+            }
+            let instructions = (frame % 3 == 2) as u32;
+            if instructions > 0 {
+                live.push(keypad.await_key_release());
+            }
+            let entry = MovieEntry::Frame {
+                keys: keypad.bits(),
+                taps: keypad.tapped_this_frame(),
+                instructions,
+            };
+            recorder.record(entry).unwrap();
+        }
+        drop(recorder);
+
+        let mut player = MoviePlayer::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut keypad = Keypad::new();
+        let mut replayed = Vec::new();
+        while let Some(MovieEntry::Frame {
+            keys,
+            taps,
+            instructions,
+        }) = player.next_entry()
+        {
+            keypad.start_frame();
+            keypad.set_bits(keys);
+            keypad.tap_keys(taps);
+            if instructions > 0 {
+                replayed.push(keypad.await_key_release());
+            }
+        }
+        assert_eq!(live, [None, Some(0x4), None, Some(0x2)]);
+        assert_eq!(replayed, live);
+    }
+}
@@ -0,0 +1,167 @@
+use sdl3::rect::Rect;
+use serde::Deserialize;
+
+/// Panels of the debugger, named like in the configuration file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PanelKind {
+    Instructions,
+    Game,
+    Controls,
+    Registers,
+    Index,
+    Timers,
+    Stack,
+}
+
+/// Panels stacked from top to bottom, `width` being relative to the other columns
+///
+/// Every panel but the game takes the height of its content, except the last one of the
+/// column which takes what is left.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Column {
+    #[serde(default = "default_width")]
+    pub width: f32,
+    pub panels: Vec<PanelKind>,
+}
+
+/// Layout of the debugger, from the `[layout]` section of the configuration file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PanelLayout {
+    /// Columns of the main window, from left to right
+    pub columns: Vec<Column>,
+    /// Columns of a second window, which stays closed when there are none
+    pub detached: Vec<Column>,
+    /// Height of the game panel, as a fraction of the height of the window
+    pub game_height: f32,
+}
+
+impl Default for PanelLayout {
+    /// The instructions on the left, the game over the controls in the middle and the
+    /// registers, index, timers and stack on the right
+    fn default() -> Self {
+        Self {
+            columns: vec![
+                Column {
+                    width: 1.0,
+                    panels: vec![PanelKind::Instructions],
+                },
+                Column {
+                    width: 2.0,
+                    panels: vec![PanelKind::Game, PanelKind::Controls],
+                },
+                Column {
+                    width: 1.0,
+                    panels: vec![
+                        PanelKind::Registers,
+                        PanelKind::Index,
+                        PanelKind::Timers,
+                        PanelKind::Stack,
+                    ],
+                },
+            ],
+            detached: Vec::new(),
+            game_height: 0.5,
+        }
+    }
+}
+
+impl PanelLayout {
+    /// Checks that the game is in the main window and that no panel is shown twice
+    pub fn validate(&self) -> Result<(), String> {
+        let main_panels = || self.columns.iter().flat_map(|column| &column.panels);
+        if !main_panels().any(|panel| *panel == PanelKind::Game) {
+            return Err("Invalid layout : the game panel must be in the main window".to_string());
+        }
+        let mut shown = Vec::new();
+        for panel in main_panels().chain(self.detached.iter().flat_map(|column| &column.panels)) {
+            if shown.contains(panel) {
+                return Err(format!("Invalid layout : {:?} panel shown twice", panel));
+            }
+            shown.push(*panel);
+        }
+        if self
+            .columns
+            .iter()
+            .chain(&self.detached)
+            .any(|column| column.width <= 0.0)
+        {
+            return Err("Invalid layout : column widths must be positive".to_string());
+        }
+        if self.game_height <= 0.0 || self.game_height > 1.0 {
+            return Err("Invalid layout : game_height must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_width() -> f32 {
+    1.0
+}
+
+/// Splits a window into its columns, in proportion to their widths
+pub fn column_rects(columns: &[Column], width: u32, height: u32) -> Vec<Rect> {
+    let total: f32 = columns.iter().map(|column| column.width).sum();
+    let mut rects = Vec::new();
+    let mut left = 0;
+    for (index, column) in columns.iter().enumerate() {
+        // The last column takes the pixels lost to rounding
+        let right = if index + 1 == columns.len() {
+            width
+        } else {
+            left + (width as f32 * column.width / total) as u32
+        };
+        rects.push(Rect::new(left as i32, 0, right - left, height));
+        left = right;
+    }
+    rects
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_layout() {
+        let layout: PanelLayout = toml::from_str(
+            r#"
+            columns = [{ width = 3, panels = ["game", "controls"] }, { panels = ["stack"] }]
+            detached = [{ panels = ["instructions", "registers"] }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(layout.columns[0].width, 3.0);
+        assert_eq!(layout.columns[1].width, 1.0);
+        assert_eq!(layout.detached[0].panels[1], PanelKind::Registers);
+        assert_eq!(layout.game_height, 0.5);
+        assert!(layout.validate().is_ok());
+        assert!(PanelLayout::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_layouts() {
+        let layout = |content| toml::from_str::<PanelLayout>(content).unwrap().validate();
+        assert!(layout(r#"columns = [{ panels = ["stack"] }]"#).is_err());
+        assert!(layout(r#"detached = [{ panels = ["game"] }]"#).is_err());
+        assert!(layout(r#"detached = [{ panels = ["stack"] }]"#).is_err());
+        assert!(layout("game_height = 1.5").is_err());
+        assert!(toml::from_str::<PanelLayout>(r#"columns = [{ panels = ["map"] }]"#).is_err());
+    }
+
+    #[test]
+    fn test_column_rects() {
+        let rects = column_rects(&PanelLayout::default().columns, 1920, 1080);
+        assert_eq!(
+            rects,
+            [
+                Rect::new(0, 0, 480, 1080),
+                Rect::new(480, 0, 960, 1080),
+                Rect::new(1440, 0, 480, 1080)
+            ]
+        );
+        let rects = column_rects(&PanelLayout::default().columns[..2], 1000, 600);
+        assert_eq!(rects[0].width() + rects[1].width(), 1000);
+    }
+}
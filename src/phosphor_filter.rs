@@ -0,0 +1,153 @@
+use crate::screen::Screen;
+use crate::screen_config::ScreenConfig;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+/// How the displayed screen follows the pixels of the previous frames, to reduce the flicker
+/// of the sprites drawn with XOR
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum FilterMode {
+    /// The screen as it is
+    #[default]
+    None,
+    /// Pixels fade out over a few frames, like on a phosphor screen
+    Decay,
+    /// Pixels lit in any of the last frames are lit
+    Or,
+}
+
+/// Settings of the `[filter]` section of the configuration file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterSettings {
+    /// Filter applied when the command line gives none [default: none]
+    #[serde(deserialize_with = "crate::config_file::value_enum")]
+    pub mode: Option<FilterMode>,
+    /// Brightness kept by a pixel from one frame to the next with the decay filter
+    pub decay: f32,
+    /// Frames merged by the OR filter
+    pub frames: usize,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        Self {
+            mode: None,
+            decay: 0.6,
+            frames: 2,
+        }
+    }
+}
+
+/// Blends the screen with the previous frames on the CPU, only for display, the emulated
+/// screen being left untouched
+pub struct PhosphorFilter {
+    mode: FilterMode,
+    decay: f32,
+    frames: usize,
+    /// Brightness of every pixel, from 0 (off) to 1 (on)
+    brightness: Vec<f32>,
+    /// Lit pixels of the last frames, the latest last
+    history: VecDeque<Vec<bool>>,
+    pixels: Vec<u8>,
+}
+
+impl PhosphorFilter {
+    pub fn new(settings: &FilterSettings) -> Self {
+        Self {
+            mode: settings.mode.unwrap_or_default(),
+            decay: settings.decay.clamp(0.0, 0.99),
+            frames: settings.frames.max(1),
+            brightness: Vec::new(),
+            history: VecDeque::new(),
+            pixels: Vec::new(),
+        }
+    }
+
+    /// Pixels to display for a new frame, in the format of `Screen::pixels`
+    pub fn apply<'p>(&'p mut self, screen: &'p Screen, screen_config: &ScreenConfig) -> &'p [u8] {
+        if self.mode == FilterMode::None {
+            return &screen.pixels;
+        }
+        let lit = screen.lit_pixels();
+        match self.mode {
+            FilterMode::None => unreachable!("The screen is returned as it is"),
+            FilterMode::Decay => {
+                self.brightness.resize(lit.len(), 0.0);
+                for (brightness, lit) in self.brightness.iter_mut().zip(lit) {
+                    *brightness = if *lit { 1.0 } else { *brightness * self.decay };
+                }
+            }
+            FilterMode::Or => {
+                self.history.push_back(lit.to_vec());
+                while self.history.len() > self.frames {
+                    self.history.pop_front();
+                }
+                let latest = self.history.back().expect("A frame was just added");
+                self.brightness = (0..latest.len())
+                    .map(|index| {
+                        let lit = self
+                            .history
+                            .iter()
+                            .any(|frame| frame.get(index) == Some(&true));
+                        if lit { 1.0 } else { 0.0 }
+                    })
+                    .collect();
+            }
+        }
+        let (on_color, off_color) = (screen_config.on_color_u8(), screen_config.off_color_u8());
+        self.pixels.clear();
+        for brightness in &self.brightness {
+            self.pixels
+                .extend(on_color.iter().zip(off_color.iter()).map(|(on, off)| {
+                    (*off as f32 + (*on as f32 - *off as f32) * brightness).round() as u8
+                }));
+        }
+        &self.pixels
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_fixtures::screen_config;
+
+    #[test]
+    fn test_decay_filter() {
+        let config = screen_config();
+        let mut screen = Screen::new(&config);
+        screen.clear();
+        let mut filter = PhosphorFilter::new(&FilterSettings {
+            mode: Some(FilterMode::Decay),
+            decay: 0.5,
+            ..FilterSettings::default()
+        });
+        screen.draw_pixel(0, 0);
+        assert_eq!(filter.apply(&screen, &config)[..3], [255, 255, 255]);
+        screen.draw_pixel(0, 0);
+        assert_eq!(filter.apply(&screen, &config)[..3], [128, 128, 128]);
+        assert_eq!(filter.apply(&screen, &config)[..3], [64, 64, 64]);
+        // The emulated screen is left as it is
+        assert!(!screen.is_pixel_on(0, 0));
+    }
+
+    #[test]
+    fn test_or_filter() {
+        let config = screen_config();
+        let mut screen = Screen::new(&config);
+        screen.clear();
+        let mut filter = PhosphorFilter::new(&FilterSettings {
+            mode: Some(FilterMode::Or),
+            ..FilterSettings::default()
+        });
+        screen.draw_pixel(0, 0);
+        filter.apply(&screen, &config);
+        screen.draw_pixel(0, 0);
+        assert_eq!(filter.apply(&screen, &config)[..3], [255, 255, 255]);
+        assert_eq!(filter.apply(&screen, &config)[..3], [0, 0, 0]);
+
+        let mut filter = PhosphorFilter::new(&FilterSettings::default());
+        assert_eq!(filter.apply(&screen, &config), &screen.pixels[..]);
+    }
+}
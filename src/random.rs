@@ -0,0 +1,164 @@
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Algorithm used by the CXNN instruction to generate random numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RandomMode {
+    /// Seeded general purpose generator
+    Seeded,
+    /// Modelled on the COSMAC VIP routine
+    CosmacVip,
+}
+
+/// Random number generator of the machine, part of the `State` so that it gets
+/// snapshotted and reset along with the rest of the machine
+#[derive(Clone)]
+pub struct Random {
+    seed: u64,
+    generator: Generator,
+}
+
+#[derive(Clone)]
+enum Generator {
+    Seeded(Box<StdRng>),
+    CosmacVip(u16),
+}
+
+impl Random {
+    pub fn new(mode: RandomMode, seed: u64) -> Self {
+        Self {
+            seed,
+            generator: Generator::new(mode, seed),
+        }
+    }
+
+    pub fn mode(&self) -> RandomMode {
+        match self.generator {
+            Generator::Seeded(_) => RandomMode::Seeded,
+            Generator::CosmacVip(_) => RandomMode::CosmacVip,
+        }
+    }
+
+    /// Restarts the random sequence from the seed so a reset replays identically
+    pub fn reset(&mut self) {
+        self.generator = Generator::new(self.mode(), self.seed);
+    }
+
+    /// Next random byte, before the mask of the CXNN instruction
+    pub fn next_byte(&mut self) -> u8 {
+        match &mut self.generator {
+            Generator::Seeded(rng) => rng.random(),
+            Generator::CosmacVip(r9) => {
+                // INC R9; GLO R9; PLO RE; GHI R3; PHI RE; GHI R9; SEX RE; ADD; STR R6;
+                // SHRC; SEX R6; ADD; PHI R9: the byte of the interpreter page pointed by the
+                // low byte of R9 is added to the high byte, and half of this sum, with the
+                // carry shifted in, is added to it again
+                *r9 = r9.wrapping_add(1);
+                let [low, high] = r9.to_le_bytes();
+                let (sum, carry) = high.overflowing_add(VIP_INTERPRETER_PAGE[low as usize]);
+                let value = (sum >> 1 | (carry as u8) << 7).wrapping_add(sum);
+                *r9 = u16::from_le_bytes([low, value]);
+                value
+            }
+        }
+    }
+}
+
+/// Second page (0x100-0x1FF) of the COSMAC VIP CHIP-8 interpreter, where the CXNN routine
+/// lives and which it reads as its source of randomness, from the interpreter listing of the
+/// VIP instruction manual
+#[rustfmt::skip]
+const VIP_INTERPRETER_PAGE: [u8; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95,
+    0xAC, 0x22, 0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6,
+    0x8A, 0xF4, 0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F,
+    0xAA, 0x0A, 0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00,
+    0x5A, 0x0E, 0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C,
+    0x9F, 0x56, 0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3,
+    0x17, 0x1A, 0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3,
+    0x17, 0x1A, 0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86,
+    0xFA, 0x0F, 0xB5, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A,
+    0x88, 0xD4, 0x45, 0x07, 0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36,
+    0x88, 0xD4, 0x3E, 0x88, 0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B,
+    0xB2, 0xFC, 0x01, 0xB5, 0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F,
+    0x3A, 0xC4, 0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07,
+    0xD2, 0x56, 0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99,
+    0xEE, 0xF4, 0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA,
+    0x0F, 0xBA, 0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0, 0x00, 0x4B,
+];
+
+impl Generator {
+    fn new(mode: RandomMode, seed: u64) -> Self {
+        match mode {
+            RandomMode::Seeded => Generator::Seeded(Box::new(StdRng::seed_from_u64(seed))),
+            RandomMode::CosmacVip => Generator::CosmacVip(seed as u16),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::{Interpreter, InterpreterVariant};
+    use crate::runner::FONT_ADDRESS;
+    use crate::test_fixtures::new_state_with;
+    use crate::vip_timing::TimingMode;
+
+    fn sequence(random: &mut Random) -> Vec<u8> {
+        (0..32).map(|_| random.next_byte()).collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        for mode in [RandomMode::Seeded, RandomMode::CosmacVip] {
+            let mut first = Random::new(mode, 1234);
+            let mut second = Random::new(mode, 1234);
+            assert_eq!(sequence(&mut first), sequence(&mut second));
+        }
+    }
+
+    #[test]
+    fn test_reset_restarts_sequence() {
+        for mode in [RandomMode::Seeded, RandomMode::CosmacVip] {
+            let mut random = Random::new(mode, 42);
+            let before = sequence(&mut random);
+            random.reset();
+            assert_eq!(before, sequence(&mut random));
+        }
+    }
+
+    #[test]
+    fn test_snapshot_keeps_generator_state() {
+        for mode in [RandomMode::Seeded, RandomMode::CosmacVip] {
+            let mut random = Random::new(mode, 7);
+            sequence(&mut random);
+            let mut snapshot = random.clone();
+            assert_eq!(sequence(&mut random), sequence(&mut snapshot));
+        }
+    }
+
+    #[test]
+    fn test_vip_values_vary_in_a_loaded_state() {
+        let mut state = new_state_with(Random::new(RandomMode::CosmacVip, 0));
+        // C0FF repeated
+        for address in (0x200..0x240).step_by(2) {
+            state.ram[address] = 0xC0;
+            state.ram[address + 1] = 0xFF;
+        }
+        let interpreter = Interpreter::new(InterpreterVariant::CosmacVip, FONT_ADDRESS);
+        let values: Vec<u8> = (0..32)
+            .map(|_| {
+                interpreter.game_step(&mut state, TimingMode::Instructions, 0);
+                state.register(0)
+            })
+            .collect();
+        // The start of the interpreter page is zeros, which repeat the last value
+        let changes = values.windows(2).filter(|pair| pair[0] != pair[1]).count();
+        assert!(changes > 24, "{values:?}");
+        let mut distinct = values.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() > 16, "{values:?}");
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_RECENT_ROMS: usize = 10;
+
+/// Most recently loaded ROMs, saved as one path per line so they survive restarts
+pub struct RecentRoms {
+    file: Option<PathBuf>,
+    pub roms: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    /// Reads the list from `file`, missing or unreadable files give an empty list
+    pub fn load(file: Option<PathBuf>) -> Self {
+        let roms = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|content| {
+                content
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(PathBuf::from)
+                    .filter(|rom| rom.is_file())
+                    .take(MAX_RECENT_ROMS)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { file, roms }
+    }
+
+    /// Moves the ROM to the top of the list and saves it
+    pub fn add(&mut self, rom: &Path) {
+        let rom = rom.canonicalize().unwrap_or_else(|_| rom.to_path_buf());
+        self.roms.retain(|recent| *recent != rom);
+        self.roms.insert(0, rom);
+        self.roms.truncate(MAX_RECENT_ROMS);
+        if let Err(e) = self.save() {
+            eprintln!("Could not save the recent ROMs : {}", e);
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(folder) = file.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let content: String = self
+            .roms
+            .iter()
+            .map(|rom| format!("{}\n", rom.display()))
+            .collect();
+        fs::write(file, content)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_recent_roms_persist() {
+        let file = env::temp_dir().join(format!("chip8-recent-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let mut recent = RecentRoms::load(Some(file.clone()));
+        assert!(recent.roms.is_empty());
+        recent.add(Path::new("roms/bc_test.ch8"));
+        recent.add(Path::new("roms/test_opcode.ch8"));
+        recent.add(Path::new("roms/bc_test.ch8"));
+
+        let names: Vec<_> = RecentRoms::load(Some(file.clone()))
+            .roms
+            .iter()
+            .map(|rom| rom.file_name().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, ["bc_test.ch8", "test_opcode.ch8"]);
+        fs::remove_file(&file).unwrap();
+    }
+}
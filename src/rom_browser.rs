@@ -0,0 +1,263 @@
+use crate::rom_profile::{RomProfiles, rom_hash};
+use sdl3::keyboard::Scancode;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const ROM_EXTENSIONS: [&str; 4] = ["ch8", "sc8", "xo8", "8o"];
+const PAGE_SIZE: usize = 10;
+
+/// Text based file explorer listing the ROMs of a folder and its sub-folders
+pub struct RomBrowser {
+    pub folder: PathBuf,
+    pub entries: Vec<BrowserEntry>,
+    pub selected: usize,
+}
+
+pub struct BrowserEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Listed on top of the folder as one of the recent ROMs
+    pub recent: bool,
+}
+
+pub enum EntryKind {
+    Parent,
+    Folder,
+    Rom { size: u64, variant: String },
+}
+
+/// What the runner should do after a key was handled by the browser
+#[derive(Debug, PartialEq, Eq)]
+pub enum BrowserAction {
+    None,
+    Close,
+    Load(PathBuf),
+}
+
+impl RomBrowser {
+    pub fn open(folder: &Path, profiles: &RomProfiles) -> Result<Self, String> {
+        let mut browser = Self {
+            folder: PathBuf::new(),
+            entries: Vec::new(),
+            selected: 0,
+        };
+        browser.change_folder(folder, profiles)?;
+        Ok(browser)
+    }
+
+    /// Lists the recent ROMs above the content of the folder, until another folder is entered
+    pub fn add_recent(&mut self, roms: &[PathBuf], profiles: &RomProfiles) {
+        let recent = roms.iter().map(|rom| BrowserEntry {
+            path: rom.clone(),
+            kind: rom_kind(rom, profiles),
+            recent: true,
+        });
+        self.entries.splice(0..0, recent);
+    }
+
+    pub fn handle_key(&mut self, scancode: Scancode, profiles: &RomProfiles) -> BrowserAction {
+        let last = self.entries.len().saturating_sub(1);
+        match scancode {
+            Scancode::Up => self.selected = self.selected.saturating_sub(1),
+            Scancode::Down => self.selected = (self.selected + 1).min(last),
+            Scancode::PageUp => self.selected = self.selected.saturating_sub(PAGE_SIZE),
+            Scancode::PageDown => self.selected = (self.selected + PAGE_SIZE).min(last),
+            Scancode::Home => self.selected = 0,
+            Scancode::End => self.selected = last,
+            Scancode::Escape | Scancode::F5 => return BrowserAction::Close,
+            Scancode::Backspace | Scancode::Left => {
+                if let Some(parent) = self.folder.parent().map(Path::to_path_buf) {
+                    self.enter(&parent, profiles);
+                }
+            }
+            Scancode::Return | Scancode::KpEnter | Scancode::Right => {
+                let Some(entry) = self.entries.get(self.selected) else {
+                    return BrowserAction::None;
+                };
+                match entry.kind {
+                    EntryKind::Rom { .. } => return BrowserAction::Load(entry.path.clone()),
+                    EntryKind::Parent | EntryKind::Folder => {
+                        let folder = entry.path.clone();
+                        self.enter(&folder, profiles);
+                    }
+                }
+            }
+            _ => {}
+        }
+        BrowserAction::None
+    }
+
+    /// Entries to display on `rows` lines, scrolled so that the selected one is visible,
+    /// along with the index of the first one
+    pub fn visible_entries(&self, rows: usize) -> (usize, &[BrowserEntry]) {
+        let rows = rows.max(1);
+        let first = (self.selected + 1).saturating_sub(rows);
+        let last = (first + rows).min(self.entries.len());
+        (first, &self.entries[first..last])
+    }
+
+    /// Unreadable folders are reported and the current one kept
+    fn enter(&mut self, folder: &Path, profiles: &RomProfiles) {
+        if let Err(e) = self.change_folder(folder, profiles) {
+            eprintln!("{}", e);
+        }
+    }
+
+    fn change_folder(&mut self, folder: &Path, profiles: &RomProfiles) -> Result<(), String> {
+        let read_error = |e: std::io::Error| format!("Could not read {} : {}", folder.display(), e);
+        let mut folders = Vec::new();
+        let mut roms = Vec::new();
+        for entry in fs::read_dir(folder).map_err(read_error)? {
+            let path = entry.map_err(read_error)?.path();
+            if path.is_dir() {
+                folders.push(BrowserEntry {
+                    path,
+                    kind: EntryKind::Folder,
+                    recent: false,
+                });
+            } else if is_rom(&path) {
+                roms.push(BrowserEntry {
+                    kind: rom_kind(&path, profiles),
+                    path,
+                    recent: false,
+                });
+            }
+        }
+        folders.sort_by(|a, b| a.path.cmp(&b.path));
+        roms.sort_by(|a, b| a.path.cmp(&b.path));
+        let folder = folder
+            .canonicalize()
+            .unwrap_or_else(|_| folder.to_path_buf());
+        self.entries = folder
+            .parent()
+            .map(|parent| BrowserEntry {
+                path: parent.to_path_buf(),
+                kind: EntryKind::Parent,
+                recent: false,
+            })
+            .into_iter()
+            .chain(folders)
+            .chain(roms)
+            .collect();
+        self.folder = folder;
+        self.selected = 0;
+        Ok(())
+    }
+}
+
+impl BrowserEntry {
+    pub fn name(&self) -> String {
+        match self.kind {
+            EntryKind::Parent => "..".to_string(),
+            _ => self
+                .path
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+fn rom_kind(path: &Path, profiles: &RomProfiles) -> EntryKind {
+    EntryKind::Rom {
+        size: fs::metadata(path).map_or(0, |metadata| metadata.len()),
+        variant: detect_variant(path, profiles),
+    }
+}
+
+fn is_rom(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        ROM_EXTENSIONS
+            .iter()
+            .any(|rom_extension| extension.eq_ignore_ascii_case(rom_extension))
+    })
+}
+
+/// Variant of the ROM profile when there is one, otherwise the platform of the extension
+fn detect_variant(path: &Path, profiles: &RomProfiles) -> String {
+    let profile_variant = fs::read(path)
+        .ok()
+        .and_then(|rom| profiles.get(&rom_hash(&rom)))
+        .and_then(|profile| profile.interpreter_variant);
+    if let Some(variant) = profile_variant {
+        return variant.to_string().to_uppercase();
+    }
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("sc8") => "SUPER-CHIP",
+        Some("xo8") => "XO-CHIP",
+        Some("8o") => "OCTO",
+        _ => "CHIP-8",
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn browser() -> RomBrowser {
+        RomBrowser::open(Path::new("roms"), &RomProfiles::default()).unwrap()
+    }
+
+    #[test]
+    fn test_list_roms() {
+        let browser = browser();
+        assert!(matches!(browser.entries[0].kind, EntryKind::Parent));
+        assert!(
+            browser
+                .entries
+                .iter()
+                .any(|entry| entry.name() == "expected" && matches!(entry.kind, EntryKind::Folder))
+        );
+        let rom = browser
+            .entries
+            .iter()
+            .find(|entry| entry.name() == "test_opcode.ch8")
+            .unwrap();
+        assert!(matches!(&rom.kind, EntryKind::Rom { size: 478, variant } if variant == "CHIP-8"));
+        assert!(
+            browser
+                .entries
+                .iter()
+                .all(|entry| entry.name() != "README.md")
+        );
+    }
+
+    #[test]
+    fn test_navigation() {
+        let profiles = RomProfiles::default();
+        let mut browser = browser();
+        let last = browser.entries.len() - 1;
+        browser.handle_key(Scancode::Up, &profiles);
+        assert_eq!(browser.selected, 0);
+        browser.handle_key(Scancode::End, &profiles);
+        assert_eq!(browser.selected, last);
+        browser.handle_key(Scancode::Down, &profiles);
+        assert_eq!(browser.selected, last);
+        assert_eq!(
+            browser.handle_key(Scancode::Return, &profiles),
+            BrowserAction::Load(browser.entries[last].path.clone())
+        );
+        assert_eq!(
+            browser.handle_key(Scancode::Escape, &profiles),
+            BrowserAction::Close
+        );
+        let (first, visible) = browser.visible_entries(2);
+        assert_eq!((first, visible.len()), (last - 1, 2));
+    }
+
+    #[test]
+    fn test_recent_roms_on_top() {
+        let profiles = RomProfiles::default();
+        let mut browser = browser();
+        let recent = PathBuf::from("roms/bc_test.ch8");
+        browser.add_recent(std::slice::from_ref(&recent), &profiles);
+        assert!(browser.entries[0].recent);
+        assert_eq!(
+            browser.handle_key(Scancode::Return, &profiles),
+            BrowserAction::Load(recent)
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use crate::config_file::{ColorsSection, value_enum};
+use crate::gamepad_mapping::GamepadMapping;
+use crate::interpreter::InterpreterVariant;
+use crate::key_mapping::{KeyLayout, KeyMapping, KeyNames};
+use crate::vip_timing::TimingMode;
+use serde::Deserialize;
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use toml_edit::DocumentMut;
+
+/// Settings that may differ from one ROM to another, every one of them being optional
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomSettings {
+    /// Only there to tell the profiles apart in the database
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub interpreter_variant: Option<InterpreterVariant>,
+    pub instructions_per_frame: Option<u8>,
+    /// Instructions per second, takes precedence over `instructions_per_frame`
+    pub speed: Option<u32>,
+    /// Measures the speed in COSMAC VIP machine cycles, which then ignores the speed and IPF
+    #[serde(default, deserialize_with = "value_enum")]
+    pub timing: Option<TimingMode>,
+    #[serde(default)]
+    pub colors: ColorsSection,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub key_layout: Option<KeyLayout>,
+    /// Keypad key (hexadecimal digit) to keyboard keys (SDL scancode names)
+    #[serde(default)]
+    pub keys: HashMap<String, KeyNames>,
+    /// Keypad key (hexadecimal digit) to gamepad buttons
+    #[serde(default)]
+    pub gamepad: HashMap<String, KeyNames>,
+}
+
+impl RomSettings {
+    /// Fills the settings missing from `self` with the ones of `fallback`
+    pub fn or(self, fallback: RomSettings) -> RomSettings {
+        let mut keys = fallback.keys;
+        keys.extend(self.keys);
+        let mut gamepad = fallback.gamepad;
+        gamepad.extend(self.gamepad);
+        // The speed and the IPF are two ways to set the same thing, they are taken together
+        let (instructions_per_frame, speed) =
+            if self.speed.is_some() || self.instructions_per_frame.is_some() {
+                (self.instructions_per_frame, self.speed)
+            } else {
+                (fallback.instructions_per_frame, fallback.speed)
+            };
+        RomSettings {
+            name: self.name.or(fallback.name),
+            interpreter_variant: self.interpreter_variant.or(fallback.interpreter_variant),
+            instructions_per_frame,
+            speed,
+            timing: self.timing.or(fallback.timing),
+            colors: ColorsSection {
+                palette: self.colors.palette.or(fallback.colors.palette),
+                on: self.colors.on.or(fallback.colors.on),
+                off: self.colors.off.or(fallback.colors.off),
+                alt: self.colors.alt.or(fallback.colors.alt),
+            },
+            key_layout: self.key_layout.or(fallback.key_layout),
+            keys,
+            gamepad,
+        }
+    }
+
+    /// Checks the keyboard and gamepad bindings, which are only resolved when the ROM is loaded
+    pub fn check_bindings(&self) -> Result<(), String> {
+        KeyMapping::new(self.key_layout.unwrap_or_default()).apply_bindings(&self.keys)?;
+        GamepadMapping::default().apply_bindings(&self.gamepad)
+    }
+
+    /// Instructions per second, from the speed or from the IPF at 60 frames per second
+    pub fn speed(&self) -> Option<u32> {
+        self.speed
+            .or(self.instructions_per_frame.map(|ipf| ipf as u32 * 60))
+    }
+}
+
+/// Per-ROM settings keyed by the SHA-1 of the ROM
+///
+/// Profiles come from a TOML file with one `["<sha1>"]` table of `RomSettings` per ROM, and
+/// optionally from the `programs.json` file of the community CHIP-8 database. The local
+/// profiles take precedence over the community ones.
+#[derive(Debug, Default)]
+pub struct RomProfiles {
+    profiles: HashMap<String, RomSettings>,
+}
+
+impl RomProfiles {
+    pub fn load(local: Option<&Path>, community: Option<&Path>) -> Result<Self, String> {
+        let mut profiles = match community {
+            Some(path) => parse_community_database(&read(path)?)
+                .map_err(|e| format!("Invalid ROM database {} : {}", path.display(), e))?,
+            None => HashMap::new(),
+        };
+        if let Some(path) = local {
+            let local: HashMap<String, RomSettings> = toml::from_str(&read(path)?)
+                .map_err(|e| format!("Invalid ROM profiles {} : {}", path.display(), e))?;
+            for (hash, settings) in local {
+                let hash = hash.to_lowercase();
+                let settings = match profiles.remove(&hash) {
+                    Some(community) => settings.or(community),
+                    None => settings,
+                };
+                profiles.insert(hash, settings);
+            }
+        }
+        for (hash, settings) in &profiles {
+            settings
+                .check_bindings()
+                .map_err(|e| format!("Invalid profile of the ROM {} : {}", hash, e))?;
+        }
+        Ok(Self { profiles })
+    }
+
+    pub fn get(&self, rom_hash: &str) -> Option<&RomSettings> {
+        self.profiles.get(rom_hash)
+    }
+
+    /// Sets the speed of a ROM and writes it into the profiles file, the rest of the file
+    /// being kept as is
+    pub fn save_speed(&mut self, path: &Path, rom_hash: &str, speed: u32) -> Result<(), String> {
+        let content = if path.is_file() {
+            read(path)?
+        } else {
+            String::new()
+        };
+        let content = set_profile_speed(&content, rom_hash, speed)
+            .map_err(|e| format!("Invalid ROM profiles {} : {}", path.display(), e))?;
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)
+                .map_err(|e| format!("Could not create {} : {}", folder.display(), e))?;
+        }
+        fs::write(path, content)
+            .map_err(|e| format!("Could not write {} : {}", path.display(), e))?;
+        self.profiles.entry(rom_hash.to_string()).or_default().speed = Some(speed);
+        Ok(())
+    }
+}
+
+/// Sets `speed` in the table of the ROM, which takes precedence over its IPF
+fn set_profile_speed(content: &str, rom_hash: &str, speed: u32) -> Result<String, String> {
+    let mut document: DocumentMut = content.parse().map_err(|e| format!("{}", e))?;
+    // The hashes are compared regardless of case when loading, a table written in uppercase
+    // is the one to update
+    let key = document
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .find(|key| key.eq_ignore_ascii_case(rom_hash))
+        .unwrap_or_else(|| rom_hash.to_string());
+    let profile = document
+        .entry(&key)
+        .or_insert(toml_edit::table())
+        .as_table_like_mut()
+        .ok_or_else(|| format!("{} is not a table", rom_hash))?;
+    profile.insert("speed", toml_edit::value(speed as i64));
+    Ok(document.to_string())
+}
+
+/// SHA-1 of the ROM, as a lowercase hexadecimal string
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("Could not read {} : {}", path.display(), e))
+}
+
+/// Reads the variant, speed and colors of every ROM of the community database `programs.json`
+///
+/// The keys are game actions there rather than keyboard keys, they are not imported.
+fn parse_community_database(content: &str) -> Result<HashMap<String, RomSettings>, String> {
+    let programs: Vec<Value> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let mut profiles = HashMap::new();
+    for program in &programs {
+        let Some(roms) = program["roms"].as_object() else {
+            continue;
+        };
+        for (hash, rom) in roms {
+            let interpreter_variant = rom["platforms"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find_map(|platform| platform.as_str().and_then(platform_variant));
+            // The tickrate is in instructions per frame, which may not fit the IPF setting
+            let speed = rom["tickrate"]
+                .as_u64()
+                .map(|tickrate| (tickrate.max(1) * 60).min(u32::MAX as u64) as u32);
+            // The background comes first, then the foreground
+            let pixel_color = |index: usize| {
+                rom["colors"]["pixels"][index]
+                    .as_str()
+                    .and_then(|color| color.parse().ok())
+            };
+            let settings = RomSettings {
+                name: program["title"].as_str().map(str::to_string),
+                interpreter_variant,
+                speed,
+                colors: ColorsSection {
+                    on: pixel_color(1),
+                    off: pixel_color(0),
+                    ..ColorsSection::default()
+                },
+                ..RomSettings::default()
+            };
+            profiles.insert(hash.to_lowercase(), settings);
+        }
+    }
+    Ok(profiles)
+}
+
+/// Variant able to run a platform of the community database, if any
+fn platform_variant(platform: &str) -> Option<InterpreterVariant> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some(InterpreterVariant::CosmacVip),
+        "modernChip8" | "chip48" => Some(InterpreterVariant::Chip48),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cli::ColorValue;
+
+    #[test]
+    fn test_rom_hash() {
+        assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn test_parse_community_database() {
+        let profiles = parse_community_database(
+            r##"[{
+                "title": "Pong",
+                "roms": {
+                    "ABCDEF": {
+                        "platforms": ["superchip", "originalChip8"],
+                        "tickrate": 7,
+                        "colors": {"pixels": ["#000000", "#FFCC00"]}
+                    },
+                    "012345": {"platforms": ["xochip"], "tickrate": 1000}
+                }
+            }]"##,
+        )
+        .unwrap();
+        let pong = &profiles["abcdef"];
+        assert_eq!(pong.name.as_deref(), Some("Pong"));
+        assert!(matches!(
+            pong.interpreter_variant,
+            Some(InterpreterVariant::CosmacVip)
+        ));
+        assert_eq!(pong.speed(), Some(420));
+        assert_eq!(pong.colors.on, Some(ColorValue::Rgb(0xFF, 0xCC, 0x00)));
+        assert_eq!(pong.colors.off, Some(ColorValue::Rgb(0, 0, 0)));
+        assert!(profiles["012345"].colors.on.is_none());
+        assert!(profiles["012345"].interpreter_variant.is_none());
+        assert_eq!(profiles["012345"].speed(), Some(60_000));
+    }
+
+    #[test]
+    fn test_set_profile_speed() {
+        let content = "# Tuned by hand\n[abc]\ninstructions_per_frame = 7 # too slow\n";
+        let content = set_profile_speed(content, "abc", 900).unwrap();
+        assert!(content.starts_with("# Tuned by hand\n"));
+        assert!(content.contains("# too slow"));
+        let content = set_profile_speed(&content, "def", 600).unwrap();
+        let profiles: HashMap<String, RomSettings> = toml::from_str(&content).unwrap();
+        assert_eq!(profiles["abc"].speed(), Some(900));
+        assert_eq!(profiles["def"].speed(), Some(600));
+
+        let content = set_profile_speed("[ABC]\nspeed = 600\n", "abc", 900).unwrap();
+        assert_eq!(content, "[ABC]\nspeed = 900\n");
+    }
+
+    #[test]
+    fn test_profiles_are_checked_on_load() {
+        let path = std::env::temp_dir().join(format!("chip8-profiles-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[abc]\nspeed = 600\n[def]\ngamepad = { \"G\" = \"a\" }\n",
+        )
+        .unwrap();
+        let result = RomProfiles::load(Some(&path), None);
+        fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().contains("def"));
+    }
+
+    #[test]
+    fn test_local_settings_take_precedence() {
+        let local: RomSettings = toml::from_str(
+            r#"
+            instructions_per_frame = 20
+            colors = { on = "white" }
+            keys = { "5" = "Space" }
+            "#,
+        )
+        .unwrap();
+        let fallback = RomSettings {
+            interpreter_variant: Some(InterpreterVariant::CosmacVip),
+            instructions_per_frame: Some(7),
+            keys: HashMap::from([("5".to_string(), KeyNames::One("W".to_string()))]),
+            ..RomSettings::default()
+        };
+        let settings = local.or(fallback);
+        assert!(matches!(
+            settings.interpreter_variant,
+            Some(InterpreterVariant::CosmacVip)
+        ));
+        assert_eq!(settings.instructions_per_frame, Some(20));
+        assert_eq!(settings.speed(), Some(1200));
+        assert!(matches!(settings.colors.on, Some(ColorValue::White)));
+        assert_eq!(settings.keys["5"], KeyNames::One("Space".to_string()));
+    }
+}
@@ -1,16 +1,36 @@
 use crate::audio_player::AudioPlayer;
+use crate::clock::{Clock, EmulationRate, TimerModel, faster_speed, slower_speed};
 use crate::config::Config;
-use crate::game_window::GameWindow;
-use crate::interpreter::{Interpreter, InterpreterVariant};
-use crate::screen_config::ScreenConfig;
+use crate::config_file::recent_roms_path;
+use crate::control_server::{ControlCommand, ControlServer};
+use crate::game_window::{GameWindow, PanelContent, SpeedStatus};
+use crate::gamepad_mapping::GamepadMapping;
+use crate::gamepads::Gamepads;
+use crate::interpreter::Interpreter;
+use crate::key_mapping::KeyMapping;
+use crate::keypad::KeyEvent;
+use crate::movie::{MovieEntry, MovieHeader, MoviePlayer, MovieRecorder};
+use crate::random::Random;
+use crate::recent_roms::RecentRoms;
+use crate::rom_browser::{BrowserAction, RomBrowser};
+use crate::rom_profile::rom_hash;
+use crate::screen_config::Colors;
+use crate::scripting::{ScriptEngine, ScriptRequest};
 use crate::state::State;
+use crate::vip_timing::{TimingMode, VIP_CYCLES_PER_SECOND};
+use rand::Rng;
 use sdl3::EventPump;
-use sdl3::event::Event;
+use sdl3::event::{Event, WindowEvent};
+use sdl3::gamepad::Button;
 use sdl3::keyboard::Scancode;
-use std::path::PathBuf;
-use std::time::{Duration, SystemTime};
-use std::{fs, io::Read};
-const FONT: [[u8; 5]; 16] = [
+use sdl3::mouse::{MouseButton, MouseUtil};
+use sdl3::sys::touch::SDL_TOUCH_MOUSEID;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+pub(crate) const FONT: [[u8; 5]; 16] = [
     [0xf0, 0x90, 0x90, 0x90, 0xf0], // 0
     [0x20, 0x60, 0x20, 0x20, 0x70], // 1
     [0xf0, 0x10, 0xf0, 0x80, 0xf0], // 2
@@ -29,141 +49,795 @@ const FONT: [[u8; 5]; 16] = [
     [0xf0, 0x80, 0xf0, 0x80, 0x80], // F
 ];
 
-const FONT_ADDRESS: u16 = 0x050;
-pub const INTERPRETER_VARIANT: InterpreterVariant = InterpreterVariant::Chip48;
+pub(crate) const FONT_ADDRESS: u16 = 0x050;
+const ROM_FOLDER: &str = "roms";
 
-const TICK_FREQUENCY: f64 = 700.0;
-const TIMER_FREQUENCY: f64 = 60.0;
 const FRAME_FREQUENCY: f64 = 60.0;
 fn frame_interval() -> Duration {
     Duration::from_secs_f64(1.0 / FRAME_FREQUENCY)
 }
+
+fn elapsed(start: SystemTime) -> Duration {
+    start.elapsed().unwrap_or(Duration::new(0, 0))
+}
+
+/// Clock cycles per second : the speed in instructions, or the machine cycles of the VIP
+fn clock_frequency(timing: TimingMode, speed: u32) -> u32 {
+    match timing {
+        TimingMode::Instructions => speed,
+        TimingMode::VipCycles => VIP_CYCLES_PER_SECOND,
+    }
+}
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ExitStatus {
     Quit,
     Reset,
+    Browse,
+    Slower,
+    Faster,
+    SaveSpeed,
+}
+
+/// Keypad keys held down on the on-screen keypad, with the mouse or with fingers
+#[derive(Default)]
+struct PointerKeys {
+    mouse: Option<u8>,
+    fingers: HashMap<u64, u8>,
+}
+
+/// Frames drawn per second, measured over about a second, and the delay between the first
+/// key press of a frame and the drawing of that frame
+struct FpsCounter {
+    frames: u32,
+    since: Instant,
+    fps: f64,
+    /// Key of the latest measure, with the delay
+    input_lag: Option<(u8, Duration)>,
+}
+
+impl FpsCounter {
+    fn new() -> Self {
+        Self {
+            frames: 0,
+            since: Instant::now(),
+            fps: 0.0,
+            input_lag: None,
+        }
+    }
+
+    /// Counts a frame just drawn, `first_press` being its first key press
+    fn count_frame(&mut self, first_press: Option<&KeyEvent>) {
+        if let Some(event) = first_press {
+            self.input_lag = Some((event.key, event.time.elapsed()));
+        }
+        self.frames += 1;
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.fps = self.frames as f64 / elapsed.as_secs_f64();
+            self.frames = 0;
+            self.since = Instant::now();
+        }
+    }
 }
 
 #[derive(Default)]
 struct RunState {
     running: bool,
     step: bool,
+    frame_advance: bool,
+}
+
+/// Loads the ROM into RAM, returns its hash
+pub(crate) fn load_rom(state: &mut State, path: PathBuf) -> std::io::Result<String> {
+    let rom = fs::read(path)?;
+    Ok(copy_rom(state, &rom))
 }
 
-fn load_rom(state: &mut State, path: PathBuf) {
-    let mut f = fs::File::open(path).expect("File not found");
-    let _ = f
-        .read(&mut state.ram[0x200..])
-        .expect("Error loading ROM into RAM");
+/// Copies the ROM already read into RAM, returns its hash
+fn copy_rom(state: &mut State, rom: &[u8]) -> String {
+    let length = rom.len().min(state.ram.len() - 0x200);
+    state.ram[0x200..0x200 + length].copy_from_slice(&rom[..length]);
     state.program_counter = 0x200u16;
+    rom_hash(rom)
 }
 
 pub struct Runner<'a> {
     config: Config,
     audio_player: AudioPlayer,
+    clock: Clock,
+    control_server: Option<ControlServer>,
     event_pump: EventPump,
+    fps_counter: FpsCounter,
+    /// Movie frames due at the current rate, fractional in slow motion
+    frame_credit: f64,
+    gamepads: Gamepads,
     game_window: GameWindow<'a>,
     interpreter: Interpreter,
-    next_timer_tick: Duration,
+    movie_player: Option<MoviePlayer>,
+    movie_recorder: Option<MovieRecorder>,
+    /// Tells which window a touch happened in
+    mouse: MouseUtil,
+    pointer_keys: PointerKeys,
+    recent_roms: RecentRoms,
+    rom_browser: Option<RomBrowser>,
+    rom_hash: String,
+    rate: EmulationRate,
     run_state: RunState,
+    script: Option<ScriptEngine>,
     state: State,
+    timing: TimingMode,
 }
 
 impl<'a> Runner<'a> {
-    pub fn init(config: Config) -> Self {
+    pub fn init(mut config: Config) -> Self {
         let sdl_context = sdl3::init().unwrap();
-        let mut state = State::new(&config.screen_config);
-
-        load_rom(&mut state, config.rom_path.clone());
+        let movie_player = config.replay_path.as_ref().map(|path| {
+            MoviePlayer::open(path).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })
+        });
+        let seed = match &movie_player {
+            Some(player) => player.header.seed,
+            None => config.seed.unwrap_or_else(|| rand::rng().random()),
+        };
+        let random_mode = movie_player
+            .as_ref()
+            .map_or(config.random_mode, |player| player.header.random_mode);
+        let mut state = State::new(&config.screen_config, Random::new(random_mode, seed));
+        let mut recent_roms = RecentRoms::load(recent_roms_path());
+        let rom_hash = match &config.rom_path {
+            Some(rom_path) => {
+                recent_roms.add(rom_path);
+                load_rom(&mut state, rom_path.clone()).unwrap_or_else(|e| {
+                    eprintln!("Could not load ROM {} : {}", rom_path.display(), e);
+                    std::process::exit(1);
+                })
+            }
+            None => String::new(),
+        };
         load_font(&mut state, FONT, FONT_ADDRESS);
-
-        let game_window = GameWindow::new(&sdl_context, &config.screen_config);
-        let interpreter = Interpreter::new(config.interpreter_variant, FONT_ADDRESS);
-        let audio_player = AudioPlayer::new(&sdl_context);
+        if let Some(player) = &movie_player
+            && player.header.rom_hash != rom_hash
+        {
+            eprintln!("The movie was recorded with a different ROM");
+            std::process::exit(1);
+        }
+        if config.rom_path.is_some() {
+            select_profile_or_defaults(&mut config, &rom_hash);
+        }
+        state.screen.set_colors(&config.screen_config);
+        let game_window = GameWindow::new(
+            &sdl_context,
+            &config.screen_config,
+            config.display_mode,
+            &config.layout,
+            &config.filter,
+        );
+        let variant = movie_player
+            .as_ref()
+            .map_or(config.interpreter_variant, |player| player.header.variant);
+        // Replays start at the speed and timing of the recording
+        let (speed, timing) = match &movie_player {
+            Some(player) => (player.header.speed, player.header.timing),
+            None => (config.speed, config.timing),
+        };
+        let movie_recorder = config.record_path.as_ref().map(|path| {
+            let header = MovieHeader {
+                seed,
+                random_mode,
+                rom_hash: rom_hash.clone(),
+                variant,
+                speed,
+                timing,
+            };
+            MovieRecorder::create(path, &header).expect("Could not create the movie file")
+        });
+        let interpreter = Interpreter::new(variant, FONT_ADDRESS);
+        let rate = config.rate;
+        // Movies always use the instructions timer model, as checked with the arguments
+        let clock = Clock::new(clock_frequency(timing, speed), config.timer_model);
+        // A replay starts right away, it can still be paused with F1
+        let run_state = RunState {
+            running: movie_player.is_some(),
+            ..RunState::default()
+        };
+        let audio_player = AudioPlayer::new(&sdl_context, &config.audio);
         let event_pump = sdl_context.event_pump().unwrap();
+        let gamepads = Gamepads::new(&sdl_context);
+        let mouse = sdl_context.mouse();
+        let script = config.script_path.as_ref().map(|path| {
+            ScriptEngine::load(path).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })
+        });
+        let control_server = config.control_address.as_ref().map(|address| {
+            ControlServer::start(address).unwrap_or_else(|e| {
+                eprintln!("Could not start the control server : {}", e);
+                std::process::exit(1);
+            })
+        });
 
-        Self {
+        let mut runner = Self {
             config,
             state,
             game_window,
             interpreter,
+            movie_player,
+            movie_recorder,
             event_pump,
-            run_state: RunState::default(),
-            next_timer_tick: Duration::new(0, 0),
+            fps_counter: FpsCounter::new(),
+            frame_credit: 0.0,
+            gamepads,
+            run_state,
+            mouse,
+            pointer_keys: PointerKeys::default(),
+            recent_roms,
+            rom_browser: None,
+            rom_hash,
+            rate,
+            timing,
             audio_player,
+            clock,
+            control_server,
+            script,
+        };
+        if runner.config.rom_path.is_none() {
+            runner.open_rom_browser();
         }
+        runner
     }
 
     pub fn run(&mut self) {
         'running: loop {
             let start = SystemTime::now();
-            // handling events
-            for event in self.event_pump.poll_iter() {
+            self.state.keypad.start_frame();
+            // handling events, collected first as the ROM browser needs the runner
+            let events: Vec<Event> = self.event_pump.poll_iter().collect();
+            for event in events {
                 match event {
+                    // The display can be switched in the ROM browser too
+                    Event::KeyDown {
+                        scancode: Some(Scancode::F11),
+                        repeat: false,
+                        ..
+                    } => {
+                        let display_mode = self.game_window.display_mode().next();
+                        self.game_window.set_display_mode(display_mode);
+                    }
+                    Event::KeyDown {
+                        scancode: Some(Scancode::Tab),
+                        repeat: false,
+                        ..
+                    } => self.cycle_palette(),
+                    Event::KeyDown {
+                        scancode: Some(key),
+                        ..
+                    } if self.rom_browser.is_some() => self.handle_browser_key(key),
                     Event::KeyDown {
                         scancode: Some(key),
                         ..
                     } => {
-                        let result = handle_key_press(&mut self.run_state, key);
+                        let result = handle_key_press(&mut self.run_state, &mut self.rate, key);
                         if let Some(status) = result {
                             match status {
                                 ExitStatus::Quit => break 'running,
                                 // Reset the state machine
                                 ExitStatus::Reset => {
-                                    self.reset_state();
+                                    if let Err(e) = self.reset_state(None) {
+                                        eprintln!("{}", e);
+                                    }
+                                    continue 'running;
+                                }
+                                ExitStatus::Browse => {
+                                    self.open_rom_browser();
                                     continue 'running;
                                 }
+                                ExitStatus::Slower => {
+                                    self.change_speed(slower_speed(self.clock.frequency()))
+                                }
+                                ExitStatus::Faster => {
+                                    self.change_speed(faster_speed(self.clock.frequency()))
+                                }
+                                ExitStatus::SaveSpeed => self.save_speed(),
                             }
                         }
-                        game_key_down(&mut self.state, key);
+                        if self.movie_player.is_none() {
+                            game_key_down(&mut self.state, &self.config.key_mapping, key);
+                        }
                     }
                     Event::KeyUp {
                         scancode: Some(key),
                         ..
-                    } => {
-                        game_key_up(&mut self.state, key);
+                    } if self.movie_player.is_none() => {
+                        game_key_up(&mut self.state, &self.config.key_mapping, key);
+                    }
+                    // Gamepads already plugged in at start are reported as added too
+                    Event::ControllerDeviceAdded { which, .. } => self.gamepads.add(which),
+                    Event::ControllerDeviceRemoved { which, .. } => self.gamepads.remove(which),
+                    Event::ControllerButtonDown { button, .. } if self.accepts_game_input() => {
+                        game_button_down(&mut self.state, &self.config.gamepad_mapping, button);
+                    }
+                    Event::ControllerButtonUp { button, .. } if self.movie_player.is_none() => {
+                        game_button_up(&mut self.state, &self.config.gamepad_mapping, button);
+                    }
+                    // Touches also come as mouse events, they are handled as touches only
+                    Event::MouseButtonDown {
+                        window_id,
+                        which,
+                        mouse_btn: MouseButton::Left,
+                        x,
+                        y,
+                        ..
+                    } if which != SDL_TOUCH_MOUSEID && self.accepts_game_input() => {
+                        if let Some(key) = self.game_window.keypad_key_at(window_id, x, y) {
+                            self.state.keypad.press_key(key);
+                            self.pointer_keys.mouse = Some(key);
+                        }
+                    }
+                    Event::MouseButtonUp {
+                        which,
+                        mouse_btn: MouseButton::Left,
+                        ..
+                    } if which != SDL_TOUCH_MOUSEID => {
+                        if let Some(key) = self.pointer_keys.mouse.take() {
+                            self.state.keypad.release_key(key);
+                        }
+                    }
+                    Event::FingerDown {
+                        finger_id, x, y, ..
+                    } if self.accepts_game_input() => {
+                        // Finger events come without their window, which has the mouse
+                        // focus once touched
+                        let touched = self.mouse.focused_window_id();
+                        let (window_id, x, y) = self.game_window.touch_position(touched, x, y);
+                        if let Some(key) = self.game_window.keypad_key_at(window_id, x, y) {
+                            self.state.keypad.press_key(key);
+                            self.pointer_keys.fingers.insert(finger_id, key);
+                        }
                     }
+                    Event::FingerUp { finger_id, .. } => {
+                        if let Some(key) = self.pointer_keys.fingers.remove(&finger_id) {
+                            self.state.keypad.release_key(key);
+                        }
+                    }
+                    Event::DropFile { filename, .. } if self.movie_player.is_none() => {
+                        self.start_rom(PathBuf::from(filename));
+                    }
+                    Event::Window {
+                        window_id,
+                        win_event: WindowEvent::Resized(width, height),
+                        ..
+                    } => self
+                        .game_window
+                        .resize(window_id, width as u32, height as u32),
+                    // Closing the detached panels keeps the emulation running
+                    Event::Window {
+                        window_id,
+                        win_event: WindowEvent::CloseRequested,
+                        ..
+                    } if !self.game_window.close(window_id) => break 'running,
                     Event::Quit { .. } => break 'running,
                     _ => {}
                 }
             }
-            let mut should_decrement = false;
-            for i in 0..self.config.instructions_per_frame {
-                if self.run_state.should_continue() {
-                    self.interpreter.game_step(&mut self.state);
-                    should_decrement = true;
-                }
+            self.handle_control_requests();
+            self.run_script(ScriptEngine::on_frame);
+            let keys = self.state.keypad.bits();
+            let taps = self.state.keypad.tapped_this_frame();
+            let executed = if self.rom_browser.is_some() {
+                0
+            } else if self.movie_player.is_some() {
+                self.replay_frames(start)
+            } else {
+                self.emulate_frame(start)
+            };
+            self.record(MovieEntry::Frame {
+                keys,
+                taps,
+                instructions: executed,
+            });
+            for _ in 0..self.clock.take_timer_ticks() {
+                self.state.decrease_timers();
             }
-            self.play_sound(should_decrement);
-            self.decrease_timers(should_decrement);
-            self.game_window
-                .update(&self.state, &self.config.screen_config);
+            let timers_running = self.rom_browser.is_none()
+                && (self.run_state.running
+                    || executed > 0
+                    || self.clock.timer_model() == TimerModel::RealTime);
+            self.play_sound(timers_running);
+            self.game_window.update(&PanelContent {
+                state: &self.state,
+                screen_config: &self.config.screen_config,
+                key_mapping: &self.config.key_mapping,
+                gamepad_mapping: self
+                    .gamepads
+                    .is_connected()
+                    .then_some(&self.config.gamepad_mapping),
+                browser: self.rom_browser.as_ref(),
+                speed_status: &SpeedStatus {
+                    rate: self.rate,
+                    speed: self.clock.frequency(),
+                    timing: self.timing,
+                    fps: self.fps_counter.fps,
+                    input_lag: self.fps_counter.input_lag,
+                },
+            });
+            let first_press = self
+                .state
+                .keypad
+                .events()
+                .iter()
+                .find(|event| event.pressed);
+            self.fps_counter
+                .count_frame(first_press.filter(|_| self.accepts_game_input()));
             self.sleep(start);
         }
     }
 
-    fn reset_state(&mut self) {
+    /// The keypad is driven by the movie during a replay and unused while browsing ROMs
+    fn accepts_game_input(&self) -> bool {
+        self.movie_player.is_none() && self.rom_browser.is_none()
+    }
+
+    /// Executes the instructions due since the previous frame at the current rate, returns
+    /// how many were executed
+    fn emulate_frame(&mut self, start: SystemTime) -> u32 {
+        if !self.run_state.running {
+            self.clock.idle();
+            // Only a frame advance (F8) or a step (F2) runs while paused
+            if !std::mem::take(&mut self.run_state.frame_advance) {
+                return if self.run_state.should_continue() {
+                    self.step_instruction();
+                    1
+                } else {
+                    0
+                };
+            }
+            self.run_state.running = true;
+            let cycles = self.clock.advance_frame();
+            let executed = self.run_cycles(cycles);
+            self.run_state.running = false;
+            return executed;
+        }
+        match self.rate.factor() {
+            Some(factor) => {
+                let cycles = self.clock.advance_real_time(factor);
+                self.run_cycles(cycles)
+            }
+            None => {
+                // Whole frames are emulated until it is time to draw the next one
+                self.clock.pause();
+                let mut executed = 0;
+                while self.run_state.running && elapsed(start) < frame_interval() {
+                    let cycles = self.clock.advance_frame();
+                    executed += self.run_cycles(cycles);
+                }
+                executed
+            }
+        }
+    }
+
+    /// Executes instructions until they cost `cycles`, stopping if the emulation gets paused,
+    /// returns how many were executed
+    fn run_cycles(&mut self, cycles: u64) -> u32 {
+        let mut executed = 0;
+        let mut spent = 0;
+        while spent < cycles && self.run_state.should_continue() {
+            spent += self.step_instruction() as u64;
+            executed += 1;
+        }
+        self.clock.overrun(spent.saturating_sub(cycles));
+        executed
+    }
+
+    /// Replays the movie frames due at the current rate, returns how many instructions were
+    /// executed
+    fn replay_frames(&mut self, start: SystemTime) -> u32 {
+        if !self.run_state.running {
+            return if std::mem::take(&mut self.run_state.frame_advance) {
+                self.replay_frame()
+            } else {
+                0
+            };
+        }
+        let mut executed = 0;
+        match self.rate.factor() {
+            Some(factor) => {
+                self.frame_credit += factor;
+                while self.frame_credit >= 1.0 && self.run_state.running {
+                    self.frame_credit -= 1.0;
+                    executed += self.replay_frame();
+                }
+            }
+            None => {
+                while self.run_state.running && elapsed(start) < frame_interval() {
+                    executed += self.replay_frame();
+                }
+            }
+        }
+        executed
+    }
+
+    /// Executes the next frame of the movie being replayed, returns how many instructions were executed
+    fn replay_frame(&mut self) -> u32 {
+        loop {
+            let Some(player) = self.movie_player.as_mut() else {
+                return 0;
+            };
+            match player.next_entry() {
+                Some(MovieEntry::Speed(speed)) => self.clock.set_frequency(speed),
+                Some(MovieEntry::Reset) => {
+                    if let Err(e) = self.reset_state(None) {
+                        eprintln!("{}", e);
+                    }
+                    self.run_state.running = true;
+                }
+                Some(MovieEntry::Frame {
+                    keys,
+                    taps,
+                    instructions,
+                }) => {
+                    self.state.keypad.set_bits(keys);
+                    self.state.keypad.tap_keys(taps);
+                    for _ in 0..instructions {
+                        self.step_instruction();
+                    }
+                    return instructions;
+                }
+                None => {
+                    println!("Replay finished");
+                    self.movie_player = None;
+                    self.run_state.running = false;
+                    return 0;
+                }
+            }
+        }
+    }
+
+    /// Executes one instruction, returns its cost in clock cycles
+    fn step_instruction(&mut self) -> u32 {
+        self.run_script(ScriptEngine::before_instruction);
+        let cost =
+            self.interpreter
+                .game_step(&mut self.state, self.timing, self.clock.cycles_to_tick());
+        for _ in 0..self.clock.cycle(cost) {
+            self.state.decrease_timers();
+        }
+        self.run_script(ScriptEngine::on_instruction);
+        cost
+    }
+
+    fn record(&mut self, entry: MovieEntry) {
+        let Some(recorder) = self.movie_recorder.as_mut() else {
+            return;
+        };
+        if let Err(e) = recorder.record(entry) {
+            eprintln!("Stopped recording the movie : {}", e);
+            self.movie_recorder = None;
+        }
+    }
+
+    /// Restarts the emulation, with another ROM when a path is given
+    ///
+    /// The ROM is read first, nothing changes when it cannot be.
+    fn reset_state(&mut self, rom_path: Option<PathBuf>) -> Result<(), String> {
+        let rom = match rom_path.as_ref().or(self.config.rom_path.as_ref()) {
+            Some(path) => Some(
+                fs::read(path)
+                    .map_err(|e| format!("Could not load ROM {} : {}", path.display(), e))?,
+            ),
+            None => None,
+        };
+        if let Some(rom_path) = rom_path {
+            if self.movie_recorder.take().is_some() {
+                eprintln!("Stopped recording the movie : another ROM was loaded");
+            }
+            self.recent_roms.add(&rom_path);
+            self.config.rom_path = Some(rom_path);
+        }
+        self.record(MovieEntry::Reset);
         self.state.reset();
+        self.clock.reset();
         let _ = std::mem::take(&mut self.run_state);
-        let r_path = self.config.rom_path.clone();
-        load_rom(&mut self.state, r_path);
         load_font(&mut self.state, FONT, FONT_ADDRESS);
+        let Some(rom) = rom else {
+            return Ok(());
+        };
+        let rom_hash = copy_rom(&mut self.state, &rom);
+        if rom_hash != self.rom_hash {
+            self.select_rom_profile(&rom_hash);
+            self.rom_hash = rom_hash;
+        }
+        Ok(())
+    }
+
+    /// Changes the number of instructions executed every second, replays keep their own speed
+    /// and the VIP timing has no other speed than the one of the VIP
+    fn change_speed(&mut self, speed: u32) {
+        if self.movie_player.is_some() || self.timing == TimingMode::VipCycles {
+            return;
+        }
+        self.clock.set_frequency(speed);
+        self.config.speed = self.clock.frequency();
+        self.record(MovieEntry::Speed(self.config.speed));
+    }
+
+    /// Saves the current speed into the profile of the ROM
+    fn save_speed(&mut self) {
+        let (Some(path), false) = (self.config.profiles_path.clone(), self.rom_hash.is_empty())
+        else {
+            return;
+        };
+        if self.timing == TimingMode::VipCycles {
+            return;
+        }
+        let speed = self.clock.frequency();
+        match self
+            .config
+            .rom_profiles
+            .save_speed(&path, &self.rom_hash, speed)
+        {
+            Ok(()) => println!("Saved {} Hz into {}", speed, path.display()),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    /// Opens the ROM browser in the ROM folder, the folder of the current ROM or `roms/`,
+    /// with the recent ROMs on top
+    fn open_rom_browser(&mut self) {
+        // Replays are bound to their ROM
+        if self.movie_player.is_some() {
+            return;
+        }
+        let folder = self.config.rom_folder.clone().unwrap_or_else(|| {
+            let rom_parent = self
+                .config
+                .rom_path
+                .as_deref()
+                .and_then(Path::parent)
+                .filter(|parent| !parent.as_os_str().is_empty());
+            match rom_parent {
+                Some(parent) => parent.to_path_buf(),
+                None if Path::new(ROM_FOLDER).is_dir() => PathBuf::from(ROM_FOLDER),
+                None => PathBuf::from("."),
+            }
+        });
+        match RomBrowser::open(&folder, &self.config.rom_profiles) {
+            Ok(mut browser) => {
+                browser.add_recent(&self.recent_roms.roms, &self.config.rom_profiles);
+                self.rom_browser = Some(browser);
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    fn handle_browser_key(&mut self, key: Scancode) {
+        let Some(browser) = self.rom_browser.as_mut() else {
+            return;
+        };
+        match browser.handle_key(key, &self.config.rom_profiles) {
+            BrowserAction::None => {}
+            // The launcher stays open until a ROM is loaded
+            BrowserAction::Close if self.config.rom_path.is_none() => {}
+            BrowserAction::Close => self.rom_browser = None,
+            BrowserAction::Load(path) => self.start_rom(path),
+        }
+    }
+
+    /// Switches to the next palette, replacing the colors of the ROM until another ROM is loaded
+    fn cycle_palette(&mut self) {
+        self.config.palette = self.config.palette.next();
+        let (on, off, alt) = self.config.palette.colors();
+        self.config.screen_config.colors = Colors::new(on, off, alt);
+        self.state.screen.set_colors(&self.config.screen_config);
+    }
+
+    /// Hot-swaps the ROM and starts it, closing the ROM browser
+    fn start_rom(&mut self, path: PathBuf) {
+        if !path.is_file() {
+            eprintln!("ROM not found : {}", path.display());
+            return;
+        }
+        if let Err(e) = self.reset_state(Some(path)) {
+            eprintln!("{}", e);
+            return;
+        }
+        self.rom_browser = None;
+        self.run_state.running = true;
+    }
+
+    /// Switches the settings to the profile of a newly loaded ROM
+    fn select_rom_profile(&mut self, rom_hash: &str) {
+        select_profile_or_defaults(&mut self.config, rom_hash);
+        self.state.screen.set_colors(&self.config.screen_config);
+        self.interpreter = Interpreter::new(self.config.interpreter_variant, FONT_ADDRESS);
+        self.timing = self.config.timing;
+        self.clock
+            .set_frequency(clock_frequency(self.timing, self.config.speed));
+    }
+
+    fn run_script(
+        &mut self,
+        hook: fn(&mut ScriptEngine, &mut State) -> Result<ScriptRequest, String>,
+    ) {
+        let Some(script) = self.script.as_mut() else {
+            return;
+        };
+        match hook(script, &mut self.state) {
+            Ok(request) => {
+                if request.pause {
+                    self.run_state.running = false;
+                }
+            }
+            // The game goes on without the script, paused to look at what went wrong
+            Err(e) => {
+                eprintln!("Disabled the script : {}", e);
+                self.script = None;
+                self.run_state.running = false;
+            }
+        }
+    }
+
+    fn handle_control_requests(&mut self) {
+        let Some(control_server) = self.control_server.take() else {
+            return;
+        };
+        for request in control_server.pending() {
+            let result = self.execute_control(&request.command);
+            request.respond(result);
+        }
+        self.control_server = Some(control_server);
+    }
+
+    fn execute_control(&mut self, command: &ControlCommand) -> Result<Value, String> {
+        match command {
+            ControlCommand::Pause => self.run_state.running = false,
+            ControlCommand::Resume => self.run_state.running = true,
+            ControlCommand::Step => self.run_state.step = true,
+            ControlCommand::Reset => self.reset_state(None)?,
+            ControlCommand::ReadMemory { address, length } => {
+                let end = address
+                    .checked_add(*length)
+                    .filter(|end| *end <= self.state.ram.len())
+                    .ok_or("Invalid address Pointer")?;
+                return Ok(json!(self.state.ram[*address..end]));
+            }
+            ControlCommand::WriteMemory { address, bytes } => {
+                let end = address
+                    .checked_add(bytes.len())
+                    .filter(|end| *end <= self.state.ram.len())
+                    .ok_or("Invalid address Pointer")?;
+                self.state.ram[*address..end].copy_from_slice(bytes);
+            }
+            ControlCommand::ReadRegisters => return Ok(read_registers(&self.state)),
+            ControlCommand::WriteRegisters(registers) => {
+                write_registers(&mut self.state, registers)?
+            }
+            ControlCommand::PressKey(key) => self.state.keypad.press_key(*key),
+            ControlCommand::ReleaseKey(key) => self.state.keypad.release_key(*key),
+            ControlCommand::Framebuffer => return Ok(read_framebuffer(&self.state)),
+            ControlCommand::LoadRom(path) => {
+                if !path.is_file() {
+                    return Err(format!("ROM not found : {}", path.display()));
+                }
+                self.reset_state(Some(path.clone()))?;
+            }
+        }
+        Ok(Value::Null)
     }
 
     fn sleep(&mut self, start: SystemTime) {
-        let elapsed = start.elapsed().unwrap_or(Duration::new(0, 0));
+        // Unthrottled frames already took a whole frame interval
+        if self.rate == EmulationRate::Unthrottled {
+            return;
+        }
         let to_sleep = frame_interval()
-            .checked_sub(elapsed)
+            .checked_sub(elapsed(start))
             .unwrap_or(Duration::new(0, 0));
         std::thread::sleep(to_sleep);
     }
 
-    fn decrease_timers(&mut self, should_decrement: bool) {
-        if should_decrement {
-            self.state.decrease_timers();
-        }
-    }
-
     fn play_sound(&self, is_playing: bool) {
         if !is_playing || self.state.sound_timer == 0 {
             self.audio_player.pause_sound()
@@ -172,7 +846,21 @@ impl<'a> Runner<'a> {
         }
     }
 }
-fn handle_key_press(run_state: &mut RunState, scancode: Scancode) -> Option<ExitStatus> {
+/// Selects the profile of the ROM, or the settings without profile when it cannot be applied
+fn select_profile_or_defaults(config: &mut Config, rom_hash: &str) {
+    if let Err(e) = config.select_rom_profile(Some(rom_hash)) {
+        eprintln!("Ignored the profile of the ROM : {}", e);
+        config
+            .select_rom_profile(None)
+            .expect("The settings without profile are checked at startup");
+    }
+}
+
+fn handle_key_press(
+    run_state: &mut RunState,
+    rate: &mut EmulationRate,
+    scancode: Scancode,
+) -> Option<ExitStatus> {
     match scancode {
         Scancode::F1 => {
             run_state.running = !run_state.running;
@@ -182,25 +870,133 @@ fn handle_key_press(run_state: &mut RunState, scancode: Scancode) -> Option<Exit
         }
         Scancode::F3 => return Some(ExitStatus::Reset),
         Scancode::F4 => return Some(ExitStatus::Quit),
+        Scancode::F5 => return Some(ExitStatus::Browse),
+        Scancode::F6 => *rate = rate.slower(),
+        Scancode::F7 => *rate = rate.faster(),
+        Scancode::F8 => {
+            run_state.frame_advance = true;
+        }
+        Scancode::F9 => return Some(ExitStatus::Slower),
+        Scancode::F10 => return Some(ExitStatus::Faster),
+        Scancode::F12 => return Some(ExitStatus::SaveSpeed),
         _ => {}
     }
     None
 }
 
-fn game_key_down(state: &mut State, code: Scancode) {
-    let result = game_key(code);
+fn game_key_down(state: &mut State, key_mapping: &KeyMapping, code: Scancode) {
+    let result = key_mapping.key(code);
     if let Some(key) = result {
         state.keypad.press_key(key);
     }
 }
-fn game_key_up(state: &mut State, code: Scancode) {
-    let result = game_key(code);
+fn game_key_up(state: &mut State, key_mapping: &KeyMapping, code: Scancode) {
+    let result = key_mapping.key(code);
     if let Some(key) = result {
         state.keypad.release_key(key);
     }
 }
 
-fn load_font(state: &mut State, font: [[u8; 5]; 16], font_addr: u16) {
+fn game_button_down(state: &mut State, gamepad_mapping: &GamepadMapping, button: Button) {
+    if let Some(key) = gamepad_mapping.key(button) {
+        state.keypad.press_key(key);
+    }
+}
+fn game_button_up(state: &mut State, gamepad_mapping: &GamepadMapping, button: Button) {
+    if let Some(key) = gamepad_mapping.key(button) {
+        state.keypad.release_key(key);
+    }
+}
+
+fn read_registers(state: &State) -> Value {
+    let mut registers = Map::new();
+    for index in 0..state.register_numbers() {
+        registers.insert(format!("v{:x}", index), json!(state.register(index)));
+    }
+    registers.insert("pc".to_string(), json!(state.program_counter));
+    registers.insert("i".to_string(), json!(state.index));
+    registers.insert("delay".to_string(), json!(state.delay_timer));
+    registers.insert("sound".to_string(), json!(state.sound_timer));
+    registers.insert(
+        "stack".to_string(),
+        json!(state.stack.read_all().collect::<Vec<_>>()),
+    );
+    Value::Object(registers)
+}
+
+/// Register named in a `write_registers` request
+enum Register {
+    ProgramCounter,
+    Index,
+    DelayTimer,
+    SoundTimer,
+    V(usize),
+}
+
+/// Writes the registers given as `{"v0": 1, "pc": 512, ...}`, names being case-insensitive
+///
+/// Every name and value is checked first, so that a bad request leaves the state untouched.
+fn write_registers(state: &mut State, registers: &Value) -> Result<(), String> {
+    let registers = registers
+        .as_object()
+        .ok_or("Expected an object of registers")?;
+    let mut updates = Vec::new();
+    for (name, value) in registers {
+        let register = match name.to_lowercase().as_str() {
+            "pc" => Register::ProgramCounter,
+            "i" => Register::Index,
+            "delay" => Register::DelayTimer,
+            "sound" => Register::SoundTimer,
+            lowercase => lowercase
+                .strip_prefix('v')
+                .and_then(|index| usize::from_str_radix(index, 16).ok())
+                .filter(|index| *index < state.register_numbers())
+                .map(Register::V)
+                .ok_or_else(|| format!("Unknown register {}", name))?,
+        };
+        let value = value
+            .as_u64()
+            .ok_or_else(|| format!("Invalid value for {}", name))?;
+        let max = match register {
+            Register::ProgramCounter | Register::Index => 0xFFF,
+            _ => u8::MAX as u64,
+        };
+        if value > max {
+            return Err(format!("Value {} out of range for {}", value, name));
+        }
+        updates.push((register, value));
+    }
+    for (register, value) in updates {
+        match register {
+            Register::ProgramCounter => state.program_counter = value as u16,
+            Register::Index => state.index = value as u16,
+            Register::DelayTimer => state.delay_timer = value as u8,
+            Register::SoundTimer => state.sound_timer = value as u8,
+            Register::V(index) => state.set_register(index, value as u8),
+        }
+    }
+    Ok(())
+}
+
+fn read_framebuffer(state: &State) -> Value {
+    let dimensions = state.screen.dimensions;
+    let rows: Vec<String> = (0..dimensions.height)
+        .map(|y| {
+            (0..dimensions.width)
+                .map(|x| {
+                    if state.screen.is_pixel_on(x, y) {
+                        '1'
+                    } else {
+                        '0'
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    json!({"width": dimensions.width, "height": dimensions.height, "rows": rows})
+}
+
+pub(crate) fn load_font(state: &mut State, font: [[u8; 5]; 16], font_addr: u16) {
     let mut index = font_addr as usize;
     for character in font.iter() {
         let end = index + character.len();
@@ -209,28 +1005,6 @@ fn load_font(state: &mut State, font: [[u8; 5]; 16], font_addr: u16) {
     }
 }
 
-fn game_key(scancode: Scancode) -> Option<u8> {
-    match scancode {
-        Scancode::_1 => Some(0x01),
-        Scancode::_2 => Some(0x02),
-        Scancode::_3 => Some(0x03),
-        Scancode::_4 => Some(0x0C),
-        Scancode::Q => Some(0x04),
-        Scancode::W => Some(0x05),
-        Scancode::E => Some(0x06),
-        Scancode::R => Some(0x0D),
-        Scancode::A => Some(0x07),
-        Scancode::S => Some(0x08),
-        Scancode::D => Some(0x09),
-        Scancode::F => Some(0x0E),
-        Scancode::Z => Some(0x0A),
-        Scancode::X => Some(0x00),
-        Scancode::C => Some(0x0B),
-        Scancode::V => Some(0x0F),
-        _ => None,
-    }
-}
-
 impl RunState {
     fn should_continue(&mut self) -> bool {
         if self.step {
@@ -240,3 +1014,62 @@ impl RunState {
         self.running
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_fixtures::new_state;
+
+    #[test]
+    fn test_write_registers() {
+        let mut state = new_state();
+        let registers = json!({"v0": 1, "VF": 2, "PC": 0x300, "I": 0xFFF, "delay": 3, "Sound": 4});
+        write_registers(&mut state, &registers).unwrap();
+        assert_eq!(state.register(0), 1);
+        assert_eq!(state.register(0xF), 2);
+        assert_eq!(state.program_counter, 0x300);
+        assert_eq!(state.index, 0xFFF);
+        assert_eq!((state.delay_timer, state.sound_timer), (3, 4));
+    }
+
+    #[test]
+    fn test_write_registers_checks_everything_first() {
+        // serde_json sorts the keys, so the valid register comes before the invalid one
+        for registers in [
+            json!({"v0": 1, "vz": 2}),
+            json!({"v0": 1, "v10": 2}),
+            json!({"v0": 1, "v1": 256}),
+            json!({"v0": 1, "v1": -1}),
+            json!({"I": 1, "PC": 0x1000}),
+            json!({"I": 0x1000}),
+            json!([1, 2]),
+        ] {
+            let mut state = new_state();
+            assert!(write_registers(&mut state, &registers).is_err());
+            assert_eq!(state.register(0), 0);
+            assert_eq!(state.program_counter, 0x200);
+            assert_eq!(state.index, 0);
+        }
+    }
+
+    #[test]
+    fn test_hotkeys_are_reserved() {
+        for scancode in (0..512).filter_map(Scancode::from_i32) {
+            let mut run_state = RunState::default();
+            let mut rate = EmulationRate::default();
+            let exit = handle_key_press(&mut run_state, &mut rate, scancode);
+            let handled = exit.is_some()
+                || run_state.running
+                || run_state.step
+                || run_state.frame_advance
+                || rate != EmulationRate::default();
+            assert_eq!(
+                handled,
+                crate::key_mapping::RESERVED_KEYS.contains(&scancode)
+                    && ![Scancode::F11, Scancode::Tab].contains(&scancode),
+                "{:?}",
+                scancode
+            );
+        }
+    }
+}
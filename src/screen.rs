@@ -2,12 +2,16 @@ extern crate sdl3;
 use crate::screen_config::{Dimensions, ScreenConfig};
 use bit_iter::BitIter;
 
+#[derive(Clone)]
 pub struct Screen {
     pub dimensions: Dimensions,
     bytes_per_pixel: usize,
     on_color: Box<[u8]>, // TODO: Remove box
     off_color: Box<[u8]>,
     pub pixels: Vec<u8>,
+    /// Whether each pixel is lit, row by row, which the colors alone cannot tell when the
+    /// palette uses the same color for both
+    lit: Vec<bool>,
 }
 
 impl Screen {
@@ -23,6 +27,7 @@ impl Screen {
                     * config.screen_dimensions.height
                     * config.bytes_per_pixel
             ],
+            lit: vec![false; config.screen_dimensions.width * config.screen_dimensions.height],
         }
     }
     pub(crate) fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
@@ -42,17 +47,31 @@ impl Screen {
     }
 
     fn flip_pixel(&mut self, index: usize) -> bool {
-        let mut flipped_off = false;
+        let flipped_off = self.lit[index];
+        self.lit[index] = !flipped_off;
+        self.paint(index);
+        flipped_off
+    }
+
+    /// Writes the color of a pixel from its state
+    fn paint(&mut self, index: usize) {
         let begin = index * self.bytes_per_pixel;
         let end = (index + 1) * self.bytes_per_pixel;
-        let pixel_color = &mut self.pixels[begin..end];
-        if *pixel_color == *self.on_color {
-            flipped_off = true;
-            pixel_color.copy_from_slice(&self.off_color)
+        let color = if self.lit[index] {
+            &self.on_color
         } else {
-            pixel_color.copy_from_slice(&self.on_color)
-        }
-        flipped_off
+            &self.off_color
+        };
+        self.pixels[begin..end].copy_from_slice(color);
+    }
+
+    pub fn is_pixel_on(&self, x: usize, y: usize) -> bool {
+        self.lit[y * self.dimensions.width + x]
+    }
+
+    /// Whether each pixel is lit, row by row
+    pub fn lit_pixels(&self) -> &[bool] {
+        &self.lit
     }
 
     pub fn draw_byte(&mut self, byte: &u8, x: usize, y: usize) -> bool {
@@ -63,12 +82,45 @@ impl Screen {
         flipped_off
     }
 
+    /// Switches to the colors of the configuration, keeping the lit pixels lit
+    pub fn set_colors(&mut self, config: &ScreenConfig) {
+        self.on_color = config.on_color_u8();
+        self.off_color = config.off_color_u8();
+        (0..self.lit.len()).for_each(|index| self.paint(index));
+    }
+
     pub fn clear(&mut self) {
-        let mut i = 0;
-        while i <= self.pixels.len() - self.bytes_per_pixel {
-            let end = i + self.bytes_per_pixel;
-            self.pixels[i..end].copy_from_slice(&self.off_color);
-            i += self.bytes_per_pixel;
+        self.lit.fill(false);
+        for pixel in self.pixels.chunks_exact_mut(self.bytes_per_pixel) {
+            pixel.copy_from_slice(&self.off_color);
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cli::ColorValue;
+    use crate::screen_config::Colors;
+    use crate::test_fixtures::screen_config;
+
+    #[test]
+    fn test_colors_keep_the_lit_pixels() {
+        let mut config = screen_config();
+        let mut screen = Screen::new(&config);
+        screen.clear();
+        screen.draw_pixel(1, 2);
+        // Both states look the same with a single color, they are still told apart
+        config.colors = Colors::new(ColorValue::Red, ColorValue::Red, ColorValue::Red);
+        screen.set_colors(&config);
+        assert!(screen.is_pixel_on(1, 2));
+        assert!(!screen.is_pixel_on(0, 0));
+        assert!(!screen.draw_pixel(0, 0));
+
+        config.colors = Colors::new(ColorValue::White, ColorValue::Black, ColorValue::Red);
+        screen.set_colors(&config);
+        assert!(screen.is_pixel_on(0, 0) && screen.is_pixel_on(1, 2));
+        assert!(!screen.is_pixel_on(2, 2));
+        assert_eq!(screen.pixels[..3], [255, 255, 255]);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::cli::ColorValue;
+use clap::ValueEnum;
 use sdl3::pixels::{Color, PixelFormat, PixelMasks};
 
 pub const SCREEN_WIDTH: usize = 64;
@@ -21,6 +22,64 @@ pub struct ScreenConfig {
     pub pixel_format: PixelFormat,
 }
 
+/// Sets of on, off and alt colors that go well together
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Palette {
+    /// Green on black
+    #[default]
+    Classic,
+    /// Amber monochrome monitor
+    Amber,
+    /// Green LCD of the early handhelds
+    LcdGreen,
+    /// Default colors of the Octo IDE
+    Octo,
+    /// White on black, with yellow highlights
+    HighContrast,
+}
+
+const PALETTES: [Palette; 5] = [
+    Palette::Classic,
+    Palette::Amber,
+    Palette::LcdGreen,
+    Palette::Octo,
+    Palette::HighContrast,
+];
+
+impl Palette {
+    /// On, off and alt colors
+    pub fn colors(self) -> (ColorValue, ColorValue, ColorValue) {
+        match self {
+            Palette::Classic => (ColorValue::Green, ColorValue::Black, ColorValue::Red),
+            Palette::Amber => (
+                ColorValue::Rgb(0xFF, 0xB0, 0x00),
+                ColorValue::Rgb(0x28, 0x18, 0x00),
+                ColorValue::Rgb(0xFF, 0xE8, 0xA0),
+            ),
+            Palette::LcdGreen => (
+                ColorValue::Rgb(0x0F, 0x38, 0x0F),
+                ColorValue::Rgb(0x9B, 0xBC, 0x0F),
+                ColorValue::Rgb(0x30, 0x62, 0x30),
+            ),
+            Palette::Octo => (
+                ColorValue::Rgb(0xFF, 0xCC, 0x00),
+                ColorValue::Rgb(0x99, 0x66, 0x00),
+                ColorValue::Rgb(0xFF, 0x66, 0x00),
+            ),
+            Palette::HighContrast => (ColorValue::White, ColorValue::Black, ColorValue::Yellow),
+        }
+    }
+
+    /// Next palette of the Tab cycle
+    pub fn next(self) -> Self {
+        let index = PALETTES
+            .iter()
+            .position(|palette| *palette == self)
+            .unwrap_or(0);
+        PALETTES[(index + 1) % PALETTES.len()]
+    }
+}
+
 #[derive(Debug)]
 pub struct Colors {
     pub on_color: Color,
@@ -50,6 +109,7 @@ fn to_sdl_color(color: ColorValue) -> Color {
         ColorValue::Black => Color::BLACK,
         ColorValue::Grey => Color::GREY,
         ColorValue::Gray => Color::GRAY,
+        ColorValue::Rgb(red, green, blue) => Color::RGB(red, green, blue),
     }
 }
 
@@ -0,0 +1,445 @@
+use crate::state::{RAM_SIZE, State};
+use rhai::{AST, CallFnOptions, Engine, EvalAltResult, Position, Scope};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::Path;
+use std::rc::Rc;
+
+const ON_FRAME: &str = "on_frame";
+const ON_INSTRUCTION: &str = "on_instruction";
+const ON_ADDRESS: &str = "on_address";
+
+/// Rhai script hooked into the run loop
+///
+/// A script may define any of the following functions, all receiving the machine as first
+/// parameter :
+/// - `on_frame(m)` called at the start of every frame
+/// - `on_instruction(m)` called after every executed instruction
+/// - `on_address(m, address)` called before executing an instruction at an address
+///   registered with `watch(address)` from the top level of the script
+pub struct ScriptEngine {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    machine: ScriptMachine,
+    watched_addresses: HashSet<u16>,
+    hooks: HashSet<&'static str>,
+}
+
+impl ScriptEngine {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        let watched = Rc::new(RefCell::new(HashSet::new()));
+        let watch_list = watched.clone();
+        engine.register_fn(
+            "watch",
+            move |address: i64| -> Result<(), Box<EvalAltResult>> {
+                watch_list.borrow_mut().insert(to_address(address)?);
+                Ok(())
+            },
+        );
+        ScriptMachine::register(&mut engine);
+
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| format!("Could not load script {} : {}", path.display(), e))?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| format!("Error in script {} : {}", path.display(), e))?;
+
+        let hooks = [ON_FRAME, ON_INSTRUCTION, ON_ADDRESS]
+            .into_iter()
+            .filter(|hook| ast.iter_functions().any(|f| f.name == *hook))
+            .collect();
+        let watched_addresses = watched.borrow().clone();
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            machine: ScriptMachine::default(),
+            watched_addresses,
+            hooks,
+        })
+    }
+
+    pub fn on_frame(&mut self, state: &mut State) -> Result<ScriptRequest, String> {
+        self.machine.borrow_mut().frame += 1;
+        self.call_hook(ON_FRAME, state, None)
+    }
+
+    pub fn on_instruction(&mut self, state: &mut State) -> Result<ScriptRequest, String> {
+        self.call_hook(ON_INSTRUCTION, state, None)
+    }
+
+    /// Calls `on_address` if the instruction about to be executed is being watched
+    pub fn before_instruction(&mut self, state: &mut State) -> Result<ScriptRequest, String> {
+        if !self.watched_addresses.contains(&state.program_counter) {
+            return Ok(ScriptRequest::default());
+        }
+        let address = state.program_counter as i64;
+        self.call_hook(ON_ADDRESS, state, Some(address))
+    }
+
+    fn call_hook(
+        &mut self,
+        hook: &str,
+        state: &mut State,
+        address: Option<i64>,
+    ) -> Result<ScriptRequest, String> {
+        if !self.hooks.contains(hook) {
+            return Ok(ScriptRequest::default());
+        }
+        self.machine.borrow_mut().load(state);
+        let options = CallFnOptions::new().eval_ast(false).rewind_scope(true);
+        let machine = self.machine.clone();
+        let result = match address {
+            Some(address) => self.engine.call_fn_with_options::<()>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                hook,
+                (machine, address),
+            ),
+            None => self.engine.call_fn_with_options::<()>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                hook,
+                (machine,),
+            ),
+        };
+        // The RAM must go back to the state even when the hook failed
+        let mut view = self.machine.borrow_mut();
+        view.apply(state);
+        result.map_err(|e| format!("Error in script hook {} : {}", hook, e))?;
+        Ok(std::mem::take(&mut view.request))
+    }
+}
+
+/// What a script asked the run loop to do once its hook returned
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScriptRequest {
+    pub pause: bool,
+}
+
+/// Machine handed to the scripts, the changes being written back to the `State` after
+/// each hook
+///
+/// The RAM is swapped with the one of the `State` for the duration of the hook rather than
+/// copied, and only the registers and keys the script changed are written back.
+#[derive(Clone)]
+struct MachineView {
+    registers: Vec<u8>,
+    ram: Box<[u8; RAM_SIZE]>,
+    program_counter: u16,
+    index: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+    /// Whether the script changed a register, the program counter, the index or a timer
+    registers_changed: bool,
+    keys: u16,
+    /// Keys pressed or released by the script
+    keys_changed: u16,
+    frame: i64,
+    request: ScriptRequest,
+}
+
+impl Default for MachineView {
+    fn default() -> Self {
+        Self {
+            registers: Vec::new(),
+            ram: Box::new([0; RAM_SIZE]),
+            program_counter: 0,
+            index: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            registers_changed: false,
+            keys: 0,
+            keys_changed: 0,
+            frame: 0,
+            request: ScriptRequest::default(),
+        }
+    }
+}
+
+impl MachineView {
+    fn load(&mut self, state: &mut State) {
+        self.registers.clear();
+        self.registers
+            .extend((0..state.register_numbers()).map(|index| state.register(index)));
+        std::mem::swap(&mut self.ram, &mut state.ram);
+        self.program_counter = state.program_counter;
+        self.index = state.index;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.registers_changed = false;
+        self.keys = state.keypad.bits();
+        self.keys_changed = 0;
+    }
+
+    fn apply(&mut self, state: &mut State) {
+        std::mem::swap(&mut self.ram, &mut state.ram);
+        if self.registers_changed {
+            for (index, value) in self.registers.iter().enumerate() {
+                state.set_register(index, *value);
+            }
+            state.program_counter = self.program_counter;
+            state.index = self.index;
+            state.delay_timer = self.delay_timer;
+            state.sound_timer = self.sound_timer;
+        }
+        for key in (0u8..16u8).filter(|key| self.keys_changed & 1 << key != 0) {
+            if self.keys & 1 << key != 0 {
+                state.keypad.press_key(key);
+            } else {
+                state.keypad.release_key(key);
+            }
+        }
+    }
+
+    fn set_key(&mut self, key: usize, pressed: bool) {
+        if pressed {
+            self.keys |= 1 << key;
+        } else {
+            self.keys &= !(1 << key);
+        }
+        self.keys_changed |= 1 << key;
+    }
+}
+
+/// Shared handle on the `MachineView` exposed to Rhai as the `Machine` type
+#[derive(Clone, Default)]
+struct ScriptMachine(Rc<RefCell<MachineView>>);
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+impl ScriptMachine {
+    fn borrow_mut(&self) -> std::cell::RefMut<'_, MachineView> {
+        self.0.borrow_mut()
+    }
+
+    fn register(engine: &mut Engine) {
+        engine
+            .register_type_with_name::<ScriptMachine>("Machine")
+            .register_fn(
+                "reg",
+                |m: &mut ScriptMachine, x: i64| -> ScriptResult<i64> {
+                    let view = m.0.borrow();
+                    let register = to_index(x, view.registers.len())?;
+                    Ok(view.registers[register] as i64)
+                },
+            )
+            .register_fn(
+                "set_reg",
+                |m: &mut ScriptMachine, x: i64, value: i64| -> ScriptResult<()> {
+                    let mut view = m.borrow_mut();
+                    let register = to_index(x, view.registers.len())?;
+                    view.registers[register] = to_byte(value)?;
+                    view.registers_changed = true;
+                    Ok(())
+                },
+            )
+            .register_fn(
+                "peek",
+                |m: &mut ScriptMachine, addr: i64| -> ScriptResult<i64> {
+                    let view = m.0.borrow();
+                    let address = to_index(addr, view.ram.len())?;
+                    Ok(view.ram[address] as i64)
+                },
+            )
+            .register_fn(
+                "poke",
+                |m: &mut ScriptMachine, addr: i64, value: i64| -> ScriptResult<()> {
+                    let mut view = m.borrow_mut();
+                    let address = to_index(addr, view.ram.len())?;
+                    view.ram[address] = to_byte(value)?;
+                    Ok(())
+                },
+            )
+            .register_get("pc", |m: &mut ScriptMachine| {
+                m.0.borrow().program_counter as i64
+            })
+            .register_set(
+                "pc",
+                |m: &mut ScriptMachine, value: i64| -> ScriptResult<()> {
+                    let mut view = m.borrow_mut();
+                    view.program_counter = to_address(value)?;
+                    view.registers_changed = true;
+                    Ok(())
+                },
+            )
+            .register_get("i", |m: &mut ScriptMachine| m.0.borrow().index as i64)
+            .register_set(
+                "i",
+                |m: &mut ScriptMachine, value: i64| -> ScriptResult<()> {
+                    let mut view = m.borrow_mut();
+                    view.index = to_address(value)?;
+                    view.registers_changed = true;
+                    Ok(())
+                },
+            )
+            .register_get("delay", |m: &mut ScriptMachine| {
+                m.0.borrow().delay_timer as i64
+            })
+            .register_set(
+                "delay",
+                |m: &mut ScriptMachine, value: i64| -> ScriptResult<()> {
+                    let mut view = m.borrow_mut();
+                    view.delay_timer = to_byte(value)?;
+                    view.registers_changed = true;
+                    Ok(())
+                },
+            )
+            .register_get("sound", |m: &mut ScriptMachine| {
+                m.0.borrow().sound_timer as i64
+            })
+            .register_set(
+                "sound",
+                |m: &mut ScriptMachine, value: i64| -> ScriptResult<()> {
+                    let mut view = m.borrow_mut();
+                    view.sound_timer = to_byte(value)?;
+                    view.registers_changed = true;
+                    Ok(())
+                },
+            )
+            .register_get("frame", |m: &mut ScriptMachine| m.0.borrow().frame)
+            .register_fn(
+                "is_pressed",
+                |m: &mut ScriptMachine, key: i64| -> ScriptResult<bool> {
+                    Ok(m.0.borrow().keys & 1 << to_index(key, 16)? != 0)
+                },
+            )
+            .register_fn(
+                "press",
+                |m: &mut ScriptMachine, key: i64| -> ScriptResult<()> {
+                    m.borrow_mut().set_key(to_index(key, 16)?, true);
+                    Ok(())
+                },
+            )
+            .register_fn(
+                "release",
+                |m: &mut ScriptMachine, key: i64| -> ScriptResult<()> {
+                    m.borrow_mut().set_key(to_index(key, 16)?, false);
+                    Ok(())
+                },
+            )
+            .register_fn("pause", |m: &mut ScriptMachine| {
+                m.borrow_mut().request.pause = true;
+            });
+    }
+}
+
+fn to_index(value: i64, len: usize) -> ScriptResult<usize> {
+    usize::try_from(value)
+        .ok()
+        .filter(|index| *index < len)
+        .ok_or_else(|| out_of_range(value))
+}
+
+fn to_byte(value: i64) -> ScriptResult<u8> {
+    u8::try_from(value).map_err(|_| out_of_range(value))
+}
+
+fn to_address(value: i64) -> ScriptResult<u16> {
+    u16::try_from(value)
+        .ok()
+        .filter(|address| *address <= 0xFFF)
+        .ok_or_else(|| out_of_range(value))
+}
+
+fn out_of_range(value: i64) -> Box<EvalAltResult> {
+    EvalAltResult::ErrorArithmetic(format!("Value {} out of range", value), Position::NONE).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_fixtures::new_state;
+
+    fn load_script(name: &str, source: &str) -> ScriptEngine {
+        let path = std::env::temp_dir().join(format!("chip8-{}-{}.rhai", name, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let script = ScriptEngine::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        script.unwrap()
+    }
+
+    #[test]
+    fn test_hooks_change_the_state() {
+        let mut script = load_script(
+            "hooks",
+            r#"
+            fn on_frame(m) {
+                m.set_reg(0xA, m.reg(0xA) + 1);
+                m.poke(0x300, m.peek(0x200));
+                m.press(5);
+            }
+            fn on_instruction(m) {
+                m.pc = 0x208;
+                m.i = 0x400;
+            }
+            "#,
+        );
+        let mut state = new_state();
+        state.ram[0x200] = 0x12;
+        state.set_register(0xA, 41);
+        script.on_frame(&mut state).unwrap();
+        assert_eq!(state.register(0xA), 42);
+        assert_eq!(state.ram[0x300], 0x12);
+        assert_eq!(state.ram[0x200], 0x12);
+        assert!(state.keypad.is_pressed(5));
+        assert_eq!(state.program_counter, 0x200);
+
+        script.on_instruction(&mut state).unwrap();
+        assert_eq!(state.program_counter, 0x208);
+        assert_eq!(state.index, 0x400);
+        assert_eq!(state.ram[0x300], 0x12);
+        assert!(state.keypad.is_pressed(5));
+    }
+
+    #[test]
+    fn test_watched_address() {
+        let mut script = load_script(
+            "watch",
+            r#"
+            watch(0x204);
+            fn on_address(m, address) {
+                m.set_reg(0, address - 0x200);
+                m.pause();
+            }
+            "#,
+        );
+        let mut state = new_state();
+        let request = script.before_instruction(&mut state).unwrap();
+        assert!(!request.pause);
+        assert_eq!(state.register(0), 0);
+
+        state.program_counter = 0x204;
+        let request = script.before_instruction(&mut state).unwrap();
+        assert!(request.pause);
+        assert_eq!(state.register(0), 4);
+    }
+
+    #[test]
+    fn test_out_of_range_values() {
+        for (name, body) in [
+            ("reg", "m.set_reg(0, 256);"),
+            ("register", "m.set_reg(16, 0);"),
+            ("poke", "m.poke(0x1000, 0);"),
+            ("byte", "m.poke(0x300, -1);"),
+            ("pc", "m.pc = 0x1000;"),
+            ("key", "m.press(16);"),
+        ] {
+            let mut script = load_script(name, &format!("fn on_frame(m) {{ {} }}", body));
+            let mut state = new_state();
+            state.ram[0x300] = 0x55;
+            assert!(script.on_frame(&mut state).is_err(), "{}", body);
+            // The RAM went back to the state despite the error
+            assert_eq!(state.ram[0x300], 0x55);
+            assert_eq!(state.program_counter, 0x200);
+        }
+        let path = std::env::temp_dir().join("chip8-missing-script.rhai");
+        assert!(ScriptEngine::load(&path).is_err());
+    }
+}
@@ -1,11 +1,12 @@
 use std::slice::Iter;
 
+#[derive(Clone)]
 pub struct Stack {
     stack: Vec<u16>,
 }
 
 impl Stack {
-    pub(crate) fn read_all(&self) -> Iter<u16> {
+    pub(crate) fn read_all(&self) -> Iter<'_, u16> {
         self.stack.iter()
     }
 }
@@ -1,13 +1,16 @@
 use crate::keypad::Keypad;
+use crate::random::Random;
 use crate::screen::Screen;
 use crate::screen_config::ScreenConfig;
 use crate::stack::Stack;
 
 const REGISTERS_SIZE: usize = 16;
-const RAM_SIZE: usize = 4096;
+pub const RAM_SIZE: usize = 4096;
 
+#[derive(Clone)]
 pub struct State {
-    pub ram: [u8; RAM_SIZE],
+    /// Boxed so that scripts can borrow it without copying it
+    pub ram: Box<[u8; RAM_SIZE]>,
     pub stack: Stack,
     pub program_counter: u16,
     pub index: u16,
@@ -16,6 +19,7 @@ pub struct State {
     registers: [u8; REGISTERS_SIZE],
     pub screen: Screen,
     pub keypad: Keypad,
+    pub random: Random,
 }
 
 impl State {
@@ -28,9 +32,9 @@ impl State {
 
 #[allow(dead_code)]
 impl State {
-    pub fn new(screen_config: &ScreenConfig) -> Self {
+    pub fn new(screen_config: &ScreenConfig, random: Random) -> Self {
         Self {
-            ram: [0; RAM_SIZE],
+            ram: Box::new([0; RAM_SIZE]),
             stack: Stack::new(),
             program_counter: 0,
             index: 0,
@@ -39,19 +43,22 @@ impl State {
             registers: [0; REGISTERS_SIZE],
             screen: Screen::new(screen_config),
             keypad: Keypad::new(),
+            random,
         }
     }
 
-    /// Reset the stack and program counters, Clears the screen, and ram
+    /// Reset the stack and program counters, Clears the screen, and ram, restarts the random sequence
     pub fn reset(&mut self) {
         let _ = std::mem::replace(&mut self.stack, Stack::new());
         let _ = std::mem::replace(&mut self.registers, [0; REGISTERS_SIZE]);
-        let _ = std::mem::replace(&mut self.ram, [0; RAM_SIZE]);
+        self.ram.fill(0);
         self.program_counter = 0;
         self.index = 0;
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.screen.clear();
+        self.keypad.cancel_wait();
+        self.random.reset();
     }
 
     pub fn register(&self, index: usize) -> u8 {
@@ -0,0 +1,22 @@
+use crate::cli::ColorValue;
+use crate::random::{Random, RandomMode};
+use crate::screen_config::{Colors, Dimensions, ScreenConfig};
+use crate::state::State;
+
+/// Screen settings of the tests, whose window is never opened
+pub fn screen_config() -> ScreenConfig {
+    let colors = Colors::new(ColorValue::White, ColorValue::Black, ColorValue::Red);
+    ScreenConfig::new(Dimensions::new(0, 0), colors)
+}
+
+/// Blank machine about to run a program at 0x200, with a seeded generator
+pub fn new_state() -> State {
+    new_state_with(Random::new(RandomMode::Seeded, 0))
+}
+
+/// Blank machine about to run a program at 0x200
+pub fn new_state_with(random: Random) -> State {
+    let mut state = State::new(&screen_config(), random);
+    state.program_counter = 0x200;
+    state
+}
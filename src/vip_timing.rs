@@ -0,0 +1,129 @@
+use crate::instruction::{Instruction, Operator};
+use crate::state::State;
+use clap::ValueEnum;
+
+/// How the speed of the emulation is measured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimingMode {
+    /// Every instruction takes the same time, the speed being set in instructions per second
+    #[default]
+    Instructions,
+    /// Every instruction takes as many machine cycles as on the COSMAC VIP
+    VipCycles,
+}
+
+/// Machine cycles of the CDP1802 per frame, 8 clock periods each at 1.76064 MHz
+const MACHINE_CYCLES_PER_FRAME: u32 = 3668;
+/// Machine cycles taken every frame by the display DMA and its interrupt routine
+const DISPLAY_CYCLES_PER_FRAME: u32 = 1024 + 46;
+/// Machine cycles left to the interpreter every second
+pub const VIP_CYCLES_PER_SECOND: u32 = (MACHINE_CYCLES_PER_FRAME - DISPLAY_CYCLES_PER_FRAME) * 60;
+
+/// Fetching and dispatching an instruction in the interpreter loop
+const FETCH_CYCLES: u32 = 40;
+/// Extra cycles of the skip instructions when the next instruction is skipped
+const SKIP_CYCLES: u32 = 4;
+const DRAW_SETUP_CYCLES: u32 = 68;
+const DRAW_ROW_CYCLES: u32 = 24;
+/// Shifting a row of the sprite by one bit, done `x % 8` times for unaligned sprites
+const DRAW_SHIFT_CYCLES: u32 = 8;
+/// Unaligned rows straddle two bytes of the display memory
+const DRAW_SECOND_BYTE_CYCLES: u32 = 16;
+/// Every unit of every digit is a subtraction loop in the VIP routine
+const DECIMAL_DIGIT_UNIT_CYCLES: u32 = 16;
+const MEMORY_COPY_REGISTER_CYCLES: u32 = 14;
+
+/// Machine cycles taken by an instruction on the COSMAC VIP, fetch included
+///
+/// The costs follow the routines of the VIP interpreter as disassembled in Laurence
+/// Scotford's "Chip-8 on the COSMAC VIP" series. They are computed before the instruction is
+/// executed, as they depend on the registers. DXYN first waits for the display interrupt,
+/// `cycles_to_interrupt` away, so that sprites are drawn between two frames.
+pub fn machine_cycles(instruction: &Instruction, state: &State, cycles_to_interrupt: u32) -> u32 {
+    let skip = |skipped: bool| if skipped { SKIP_CYCLES } else { 0 };
+    let execution = match instruction {
+        Instruction::ClearScreen => 3078,
+        Instruction::SubroutineReturn => 10,
+        Instruction::Jump(_) => 12,
+        Instruction::SubroutineCall(_) => 26,
+        Instruction::SkipEqualValue(rx, value, condition) => {
+            10 + skip((state.register(*rx) == *value) == *condition)
+        }
+        Instruction::SkipEqualRegister(rx, ry, condition) => {
+            14 + skip((state.register(*rx) == state.register(*ry)) == *condition)
+        }
+        Instruction::SetRegister(_, _) => 6,
+        Instruction::AddValueRegister(_, _) => 10,
+        Instruction::Arithmetic(_, _, Operator::Set) => 12,
+        Instruction::Arithmetic(_, _, _) => 44,
+        Instruction::SetIndex(_) => 12,
+        Instruction::JumpOffset(_, _) => 22,
+        Instruction::Random(_, _) => 36,
+        Instruction::Draw(rx, _, height) => {
+            cycles_to_interrupt + draw_cycles(state.register(*rx), *height)
+        }
+        Instruction::SkipIfKey(rx, if_pressed) => {
+            14 + skip(state.keypad.is_pressed(state.register(*rx)) == *if_pressed)
+        }
+        Instruction::ReadDelayTimer(_)
+        | Instruction::SetDelayTimer(_)
+        | Instruction::SetSoundTimer(_) => 10,
+        Instruction::AddToIndex(_) => 16,
+        Instruction::GetKey(_) => 20,
+        Instruction::FontCharacter(_) => 20,
+        Instruction::DecimalConversion(rx) => {
+            let value = state.register(*rx);
+            let units = (value / 100 + value / 10 % 10 + value % 10) as u32;
+            72 + DECIMAL_DIGIT_UNIT_CYCLES * units
+        }
+        Instruction::StoreRegisters(rx) | Instruction::LoadRegisters(rx) => {
+            14 + MEMORY_COPY_REGISTER_CYCLES * (*rx as u32 + 1)
+        }
+        Instruction::System | Instruction::Unsupported(_, _) => 0,
+    };
+    FETCH_CYCLES + execution
+}
+
+/// Drawing costs more for taller sprites, and for sprites that are not aligned on a byte of
+/// the display memory as every row is shifted bit by bit and written over two bytes
+fn draw_cycles(x: u8, height: u8) -> u32 {
+    let shift = (x % 8) as u32;
+    let row = match shift {
+        0 => DRAW_ROW_CYCLES,
+        shift => DRAW_ROW_CYCLES + DRAW_SHIFT_CYCLES * shift + DRAW_SECOND_BYTE_CYCLES,
+    };
+    DRAW_SETUP_CYCLES + row * height as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_fixtures::new_state;
+
+    #[test]
+    fn test_draw_waits_for_the_interrupt() {
+        let state = new_state();
+        let draw = Instruction::Draw(0, 1, 5);
+        assert_eq!(
+            machine_cycles(&draw, &state, 1000) - machine_cycles(&draw, &state, 0),
+            1000
+        );
+        assert_eq!(
+            machine_cycles(&draw, &state, 0),
+            FETCH_CYCLES + draw_cycles(0, 5)
+        );
+        // Other instructions do not wait
+        let set = Instruction::SetRegister(0, 1);
+        assert_eq!(machine_cycles(&set, &state, 1000), FETCH_CYCLES + 6);
+    }
+
+    #[test]
+    fn test_draw_cycles() {
+        assert_eq!(draw_cycles(8, 0), DRAW_SETUP_CYCLES);
+        assert!(draw_cycles(8, 15) > draw_cycles(8, 5));
+        // Unaligned sprites cost more, the further from the byte boundary the more
+        assert!(draw_cycles(9, 5) > draw_cycles(8, 5));
+        assert!(draw_cycles(15, 5) > draw_cycles(9, 5));
+        assert_eq!(draw_cycles(16, 5), draw_cycles(0, 5));
+    }
+}